@@ -8,9 +8,10 @@ use log::error;
 use near_primitives::block::BlockValidityError;
 use near_primitives::challenge::{ChunkProofs, ChunkState};
 use near_primitives::errors::{EpochError, StorageError};
+use near_primitives::hash::CryptoHash;
 use near_primitives::serialize::to_base;
 use near_primitives::sharding::{ChunkHash, ShardChunkHeader};
-use near_primitives::types::{BlockHeight, EpochId, ShardId};
+use near_primitives::types::{BlockHeight, BlockHeightDelta, EpochId, ShardId};
 
 #[derive(thiserror::Error, Debug)]
 pub enum QueryError {
@@ -82,6 +83,9 @@ pub enum ErrorKind {
     /// Block time is before parent block time.
     #[fail(display = "Invalid Block Time: block time {} before previous {}", _1, _0)]
     InvalidBlockPastTime(DateTime<Utc>, DateTime<Utc>),
+    /// Block time is the same as parent block time.
+    #[fail(display = "Invalid Block Time: block time {} same as previous", _0)]
+    DuplicateBlockTime(DateTime<Utc>),
     /// Block time is from too much in the future.
     #[fail(display = "Invalid Block Time: Too far in the future: {}", _0)]
     InvalidBlockFutureTime(DateTime<Utc>),
@@ -130,6 +134,9 @@ pub enum ErrorKind {
     /// Incorrect number of chunk headers
     #[fail(display = "Incorrect Number of Chunk Headers")]
     IncorrectNumberOfChunkHeaders,
+    /// Headers received for sync don't form a chain.
+    #[fail(display = "Invalid Header Chain: {} doesn't follow {}", _1, _0)]
+    InvalidHeaderChain(CryptoHash, CryptoHash),
     /// Invalid chunk.
     #[fail(display = "Invalid Chunk")]
     InvalidChunk,
@@ -202,6 +209,9 @@ pub enum ErrorKind {
     /// A challenged block is on the chain that was attempted to become the head
     #[fail(display = "Challenged block on chain")]
     ChallengedBlockOnChain,
+    /// Switching the head to the block would revert more blocks than allowed.
+    #[fail(display = "Reorg of depth {} is deeper than the limit {}", depth, limit)]
+    ReorgTooDeep { depth: BlockHeightDelta, limit: BlockHeightDelta },
     /// IO Error.
     #[fail(display = "IO Error: {}", _0)]
     IOErr(String),
@@ -211,6 +221,9 @@ pub enum ErrorKind {
     /// Storage error. Used for internal passing the error.
     #[fail(display = "Storage Error: {}", _0)]
     StorageError(StorageError),
+    /// Runtime failed to apply a chunk.
+    #[fail(display = "Runtime Apply Failed: {}", _0)]
+    RuntimeApplyFailed(ApplyChunkFailure),
     /// GC error.
     #[fail(display = "GC Error: {}", _0)]
     GCError(String),
@@ -219,6 +232,27 @@ pub enum ErrorKind {
     Other(String),
 }
 
+/// Why the runtime failed to apply a chunk.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ApplyChunkFailure {
+    /// Reading or writing the state failed on this node. Applying may succeed later.
+    StorageError(StorageError),
+    /// The chunk can't be applied by any node, e.g. it has invalid transactions.
+    InvalidChunk(String),
+    /// Anything else reported by the runtime.
+    Other(String),
+}
+
+impl Display for ApplyChunkFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyChunkFailure::StorageError(err) => write!(f, "Storage Error: {}", err),
+            ApplyChunkFailure::InvalidChunk(msg) => write!(f, "Invalid Chunk: {}", msg),
+            ApplyChunkFailure::Other(msg) => write!(f, "Other Error: {}", msg),
+        }
+    }
+}
+
 /// For now StorageError can happen at any time from ViewClient because of
 /// the used isolation level + running ViewClient in a separate thread.
 pub trait LogTransientStorageError {
@@ -273,8 +307,12 @@ impl Error {
             | ErrorKind::ValidatorError(_)
             | ErrorKind::EpochOutOfBounds(_)
             | ErrorKind::ChallengedBlockOnChain
+            | ErrorKind::ReorgTooDeep { .. }
+            | ErrorKind::DuplicateBlockTime(_)
             | ErrorKind::StorageError(_)
             | ErrorKind::GCError(_)
+            | ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::StorageError(_))
+            | ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::Other(_))
             | ErrorKind::DBNotFoundErr(_) => false,
             ErrorKind::InvalidBlockPastTime(_, _)
             | ErrorKind::InvalidBlockFutureTime(_)
@@ -296,6 +334,7 @@ impl Error {
             | ErrorKind::InvalidChallenge
             | ErrorKind::MaliciousChallenge
             | ErrorKind::IncorrectNumberOfChunkHeaders
+            | ErrorKind::InvalidHeaderChain(_, _)
             | ErrorKind::InvalidEpochHash
             | ErrorKind::InvalidNextBPHash
             | ErrorKind::NotEnoughApprovals
@@ -312,13 +351,18 @@ impl Error {
             | ErrorKind::InvalidRandomnessBeaconOutput
             | ErrorKind::InvalidBlockMerkleRoot
             | ErrorKind::NotAValidator
+            | ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::InvalidChunk(_))
             | ErrorKind::InvalidChallengeRoot => true,
         }
     }
 
     pub fn is_error(&self) -> bool {
         match self.kind() {
-            ErrorKind::IOErr(_) | ErrorKind::Other(_) | ErrorKind::DBNotFoundErr(_) => true,
+            ErrorKind::IOErr(_)
+            | ErrorKind::Other(_)
+            | ErrorKind::DBNotFoundErr(_)
+            | ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::StorageError(_))
+            | ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::Other(_)) => true,
             _ => false,
         }
     }
@@ -1,3 +1,3 @@
-pub use error::{Error, ErrorKind};
+pub use error::{ApplyChunkFailure, Error, ErrorKind};
 
 pub mod error;
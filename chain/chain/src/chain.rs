@@ -1,21 +1,27 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::mem::{self, Discriminant};
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::time::{Duration as TimeDuration, Instant};
 
 use borsh::BorshSerialize;
+use cached::{Cached, SizedCache};
 use chrono::Duration;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
+use near_crypto::Signature;
 use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use rand::SeedableRng;
+use rayon::prelude::*;
 use tracing::{debug, error, info, warn};
 
-use near_chain_primitives::error::{Error, ErrorKind, LogTransientStorageError};
+use near_chain_primitives::error::{ApplyChunkFailure, Error, ErrorKind, LogTransientStorageError};
 use near_primitives::block::{genesis_chunks, Tip};
 use near_primitives::challenge::{
-    BlockDoubleSign, Challenge, ChallengeBody, ChallengesResult, ChunkProofs, ChunkState,
-    MaybeEncodedShardChunk, SlashedValidator,
+    Challenge, ChallengeBody, ChallengesResult, ChunkProofs, ChunkState, MaybeEncodedShardChunk,
+    SlashedValidator,
 };
 use near_primitives::checked_feature;
 use near_primitives::hash::{hash, CryptoHash};
@@ -32,11 +38,11 @@ use near_primitives::syncing::{
     ShardStateSyncResponseHeader, ShardStateSyncResponseHeaderV1, ShardStateSyncResponseHeaderV2,
     StateHeaderKey, StatePartKey,
 };
-use near_primitives::transaction::ExecutionOutcomeWithIdAndProof;
+use near_primitives::transaction::{ExecutionOutcomeWithIdAndProof, SignedTransaction};
 use near_primitives::types::chunk_extra::ChunkExtra;
 use near_primitives::types::{
-    AccountId, Balance, BlockExtra, BlockHeight, BlockHeightDelta, EpochId, MerkleHash, NumBlocks,
-    ShardId,
+    AccountId, Balance, BlockExtra, BlockHeight, BlockHeightDelta, EpochId, Gas, MerkleHash,
+    NumBlocks, NumShards, ShardId, StateRoot,
 };
 use near_primitives::unwrap_or_return;
 #[cfg(feature = "protocol_feature_block_header_v3")]
@@ -46,17 +52,21 @@ use near_primitives::views::{
     FinalExecutionOutcomeWithReceiptView, FinalExecutionStatus, LightClientBlockView,
     SignedTransactionView,
 };
-use near_store::{ColState, ColStateHeaders, ColStateParts, ShardTries, StoreUpdate};
+use near_store::{
+    ColBlockHeader, ColState, ColStateHeaders, ColStateParts, ShardTries, StoreUpdate,
+};
 
 use near_primitives::state_record::StateRecord;
 
 use crate::lightclient::get_epoch_block_producers_view;
 use crate::migrations::check_if_block_is_first_with_chunk_of_version;
 use crate::missing_chunks::{BlockLike, MissingChunksPool};
-use crate::store::{ChainStore, ChainStoreAccess, ChainStoreUpdate, GCMode};
+use crate::store::{option_to_not_found, ChainStore, ChainStoreAccess, ChainStoreUpdate, GCMode};
 use crate::types::{
     AcceptedBlock, ApplyTransactionResult, Block, BlockEconomicsConfig, BlockHeader,
-    BlockHeaderInfo, BlockStatus, ChainGenesis, Provenance, RuntimeAdapter,
+    BlockHeaderInfo, BlockStatus, ChainEvent, ChainEventHandler, ChainGenesis, Clock,
+    EquivocationProof, ProcessBlockResult, Provenance, RealClock, ReorgEvent, RuntimeAdapter,
+    TxInclusionProof,
 };
 use crate::validate::{
     validate_challenge, validate_chunk_proofs, validate_chunk_with_chunk_extra,
@@ -73,8 +83,30 @@ pub const MAX_ORPHAN_SIZE: usize = 1024;
 /// Maximum age of orhpan to store in the chain.
 const MAX_ORPHAN_AGE_SECS: u64 = 300;
 
-/// Refuse blocks more than this many block intervals in the future (as in bitcoin).
-const ACCEPTABLE_TIME_DIFFERENCE: i64 = 12 * 10;
+/// Number of recently rejected blocks remembered to drop their orphaned descendants.
+const INVALID_BLOCKS_CACHE_SIZE: usize = 1000;
+
+/// Number of headers validated ahead of their blocks that are remembered.
+const VALIDATED_HEADERS_CACHE_SIZE: usize = 1000;
+
+/// Number of orphans waiting on the same missing parent above which a warning is logged.
+const AWAITED_PARENT_WARN_THRESHOLD: usize = 10;
+
+/// Delay before missing chunks of a block are requested again for the first time. It doubles
+/// with every further request, up to `CHUNK_REREQUEST_MAX_DELAY`.
+const CHUNK_REREQUEST_BASE_DELAY: TimeDuration = TimeDuration::from_millis(200);
+
+/// Maximum delay between two requests of the missing chunks of a block.
+const CHUNK_REREQUEST_MAX_DELAY: TimeDuration = TimeDuration::from_secs(10);
+
+/// Maximum total serialized size of orphans chain can store.
+const MAX_ORPHAN_BYTES: usize = 512 * 1024 * 1024;
+
+/// Refuse blocks more than this many seconds in the future (as in bitcoin) by default.
+pub const ACCEPTABLE_TIME_DIFFERENCE: i64 = 12 * 10;
+
+/// Maximum number of hashes looked up when searching for a common header with a peer.
+pub const MAX_COMMON_HEADER_LOOKUPS: usize = 512;
 
 /// Over this block height delta in advance if we are not chunk producer - route tx to upcoming validators.
 pub const TX_ROUTING_HEIGHT_HORIZON: BlockHeightDelta = 4;
@@ -88,6 +120,9 @@ pub const NUM_EPOCHS_TO_KEEP_STORE_DATA: u64 = 5;
 /// Maximum number of height to go through at each step when cleaning forks during garbage collection.
 const GC_FORK_CLEAN_STEP: u64 = 1000;
 
+/// Callback invoked with transactions of new chunks before they are applied.
+pub type TxObserver = Box<dyn FnMut(&CryptoHash, &SignedTransaction) + Send>;
+
 enum ApplyChunksMode {
     ThisEpoch,
     NextEpoch,
@@ -97,6 +132,15 @@ pub struct Orphan {
     block: Block,
     provenance: Provenance,
     added: Instant,
+    /// Serialized size of the block, counted against `OrphanPoolConfig::max_bytes`.
+    size: usize,
+}
+
+impl Orphan {
+    fn new(block: Block, provenance: Provenance, added: Instant) -> Self {
+        let size = block.try_to_vec().expect("Failed to serialize").len();
+        Orphan { block, provenance, added, size }
+    }
 }
 
 impl BlockLike for Orphan {
@@ -109,19 +153,113 @@ impl BlockLike for Orphan {
     }
 }
 
+/// Limits of the orphan pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrphanPoolConfig {
+    /// Number of orphans above which eviction kicks in.
+    pub max_size: usize,
+    /// Orphans older than this are dropped on eviction.
+    pub max_age_secs: u64,
+    /// Total serialized size of orphans above which eviction kicks in.
+    pub max_bytes: usize,
+}
+
+impl Default for OrphanPoolConfig {
+    fn default() -> Self {
+        OrphanPoolConfig {
+            max_size: MAX_ORPHAN_SIZE,
+            max_age_secs: MAX_ORPHAN_AGE_SECS,
+            max_bytes: MAX_ORPHAN_BYTES,
+        }
+    }
+}
+
+/// Limits for a single `Chain::run_gc` pass.
+#[derive(Debug, Clone)]
+pub struct GcPolicy {
+    /// Maximum number of blocks removed in this pass, forks and canonical chain combined.
+    /// Canonical blocks are removed up to the runtime's gc stop height.
+    pub gc_blocks_limit: NumBlocks,
+    /// Whether to remove blocks on forks below the fork tail.
+    pub clear_forks: bool,
+    /// Orphans older than this are dropped from the orphan pool.
+    pub max_orphan_age: Option<TimeDuration>,
+}
+
+/// What a `Chain::run_gc` pass removed. Transaction outcomes are removed together with
+/// their blocks, so they are accounted in the block counts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub fork_blocks: NumBlocks,
+    pub canonical_blocks: NumBlocks,
+    pub orphans: usize,
+}
+
+/// Problem found by `Chain::verify_store_integrity`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// The height index points to a block that is not stored.
+    MissingBlock { height: BlockHeight, hash: CryptoHash },
+    /// The header of the block's previous block is not stored.
+    MissingPrevHeader { height: BlockHeight, hash: CryptoHash, prev_hash: CryptoHash },
+    /// The block's `prev_state_root` doesn't match the state roots of its chunks.
+    StateRootMismatch { height: BlockHeight, hash: CryptoHash },
+}
+
+/// Counts of block processing outcomes since the chain was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainMetrics {
+    pub blocks_accepted: u64,
+    pub orphans_added: u64,
+    pub blocks_missing_chunks: u64,
+    pub reorgs: u64,
+    /// Total time spent applying chunks per shard. Only measured while
+    /// `Chain::apply_time_warn_threshold` is set.
+    pub apply_time_per_shard: HashMap<ShardId, TimeDuration>,
+    invalid_blocks: HashMap<Discriminant<ErrorKind>, u64>,
+}
+
+impl ChainMetrics {
+    /// Number of blocks rejected as invalid with an error of the same variant as `kind`.
+    pub fn invalid_blocks(&self, kind: &ErrorKind) -> u64 {
+        self.invalid_blocks.get(&mem::discriminant(kind)).copied().unwrap_or_default()
+    }
+
+    /// Number of blocks rejected as invalid, of any error variant.
+    pub fn invalid_blocks_total(&self) -> u64 {
+        self.invalid_blocks.values().sum()
+    }
+
+    fn record_accepted(&mut self, status: &BlockStatus) {
+        self.blocks_accepted += 1;
+        if let BlockStatus::Reorg(_) = status {
+            self.reorgs += 1;
+        }
+    }
+}
+
 pub struct OrphanBlockPool {
+    config: OrphanPoolConfig,
     orphans: HashMap<CryptoHash, Orphan>,
     height_idx: HashMap<BlockHeight, Vec<CryptoHash>>,
     prev_hash_idx: HashMap<CryptoHash, Vec<CryptoHash>>,
+    /// Total serialized size of orphans in the pool.
+    bytes: usize,
     evicted: usize,
 }
 
 impl OrphanBlockPool {
     pub fn new() -> OrphanBlockPool {
+        OrphanBlockPool::with_config(OrphanPoolConfig::default())
+    }
+
+    pub fn with_config(config: OrphanPoolConfig) -> OrphanBlockPool {
         OrphanBlockPool {
+            config,
             orphans: HashMap::default(),
             height_idx: HashMap::default(),
             prev_hash_idx: HashMap::default(),
+            bytes: 0,
             evicted: 0,
         }
     }
@@ -134,59 +272,156 @@ impl OrphanBlockPool {
         self.evicted
     }
 
-    fn add(&mut self, orphan: Orphan) {
+    fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn is_over_budget(&self) -> bool {
+        self.orphans.len() > self.config.max_size || self.bytes > self.config.max_bytes
+    }
+
+    /// Removes orphan from the pool, indices are cleaned up separately.
+    fn remove(&mut self, hash: &CryptoHash) -> Option<Orphan> {
+        let orphan = self.orphans.remove(hash)?;
+        self.bytes = self.bytes.saturating_sub(orphan.size);
+        Some(orphan)
+    }
+
+    fn remove_expired(&mut self, max_age: TimeDuration) -> Vec<Orphan> {
+        let expired = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.added.elapsed() >= max_age)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        expired.iter().filter_map(|hash| self.remove(hash)).collect()
+    }
+
+    /// Adds orphan to the pool. Returns (hash, prev_hash) of orphans evicted to make room.
+    fn add(&mut self, orphan: Orphan) -> Vec<(CryptoHash, CryptoHash)> {
         let height_hashes =
             self.height_idx.entry(orphan.block.header().height()).or_insert_with(|| vec![]);
         height_hashes.push(*orphan.block.hash());
         let prev_hash_entries =
             self.prev_hash_idx.entry(*orphan.block.header().prev_hash()).or_insert_with(|| vec![]);
         prev_hash_entries.push(*orphan.block.hash());
-        self.orphans.insert(*orphan.block.hash(), orphan);
+        self.bytes += orphan.size;
+        if let Some(old_orphan) = self.orphans.insert(*orphan.block.hash(), orphan) {
+            self.bytes = self.bytes.saturating_sub(old_orphan.size);
+        }
 
-        if self.orphans.len() > MAX_ORPHAN_SIZE {
+        let mut evicted = vec![];
+        if self.is_over_budget() {
             let old_len = self.orphans.len();
 
-            self.orphans.retain(|_, ref mut x| {
-                x.added.elapsed() < TimeDuration::from_secs(MAX_ORPHAN_AGE_SECS)
-            });
+            evicted = self.remove_expired(TimeDuration::from_secs(self.config.max_age_secs));
             let mut heights = self.height_idx.keys().cloned().collect::<Vec<u64>>();
             heights.sort_unstable();
-            let mut removed_hashes: HashSet<CryptoHash> = HashSet::default();
             for h in heights.iter().rev() {
                 if let Some(hash) = self.height_idx.remove(h) {
                     for h in hash {
-                        let _ = self.orphans.remove(&h);
-                        removed_hashes.insert(h);
+                        evicted.extend(self.remove(&h));
                     }
                 }
-                if self.orphans.len() < MAX_ORPHAN_SIZE {
+                if self.orphans.len() < self.config.max_size && self.bytes <= self.config.max_bytes
+                {
                     break;
                 }
             }
-            self.height_idx.retain(|_, ref mut xs| xs.iter().any(|x| !removed_hashes.contains(&x)));
-            self.prev_hash_idx
-                .retain(|_, ref mut xs| xs.iter().any(|x| !removed_hashes.contains(&x)));
 
             self.evicted += old_len - self.orphans.len();
+            self.compact();
         }
+        evicted
+            .into_iter()
+            .map(|orphan| (*orphan.block.hash(), *orphan.block.header().prev_hash()))
+            .collect()
+    }
+
+    /// Drops index entries that no longer point to any orphan and releases unused capacity.
+    fn compact(&mut self) {
+        let orphans = &self.orphans;
+        self.height_idx.retain(|_, hashes| {
+            hashes.retain(|hash| orphans.contains_key(hash));
+            !hashes.is_empty()
+        });
+        self.prev_hash_idx.retain(|_, hashes| {
+            hashes.retain(|hash| orphans.contains_key(hash));
+            !hashes.is_empty()
+        });
+        self.orphans.shrink_to_fit();
+        self.height_idx.shrink_to_fit();
+        self.prev_hash_idx.shrink_to_fit();
     }
 
     pub fn contains(&self, hash: &CryptoHash) -> bool {
         self.orphans.contains_key(hash)
     }
 
-    pub fn remove_by_prev_hash(&mut self, prev_hash: CryptoHash) -> Option<Vec<Orphan>> {
+    /// Drops orphans that were added more than `max_age` ago. Returns number of orphans dropped.
+    fn prune_older_than(&mut self, max_age: TimeDuration) -> usize {
+        let removed = self.remove_expired(max_age).len();
+        self.compact();
+        removed
+    }
+
+    /// Returns number of orphans by provenance: (NONE, SYNC, PRODUCED).
+    pub fn orphans_by_provenance(&self) -> (usize, usize, usize) {
+        self.orphans.values().fold((0, 0, 0), |(none, sync, produced), orphan| {
+            match orphan.provenance {
+                Provenance::NONE => (none + 1, sync, produced),
+                Provenance::SYNC => (none, sync + 1, produced),
+                Provenance::PRODUCED => (none, sync, produced + 1),
+            }
+        })
+    }
+
+    /// Iterates over held orphans as (hash, height, provenance) without cloning blocks.
+    pub fn iter(&self) -> impl Iterator<Item = (&CryptoHash, BlockHeight, &Provenance)> {
+        self.orphans
+            .iter()
+            .map(|(hash, orphan)| (hash, orphan.block.header().height(), &orphan.provenance))
+    }
+
+    /// Returns hashes of orphans that were added within `[start, end]`.
+    pub fn orphans_added_between(&self, start: Instant, end: Instant) -> Vec<CryptoHash> {
+        self.orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.added >= start && orphan.added <= end)
+            .map(|(hash, _)| *hash)
+            .collect()
+    }
+
+    /// Returns the parent hash with the most orphans waiting on it, together with their number.
+    /// Ties are broken by the smaller hash.
+    pub fn most_awaited_parent(&self) -> Option<(CryptoHash, usize)> {
+        self.prev_hash_idx
+            .iter()
+            .map(|(prev_hash, hashes)| {
+                (*prev_hash, hashes.iter().filter(|hash| self.orphans.contains_key(hash)).count())
+            })
+            .filter(|(_, count)| *count > 0)
+            .max_by(|(hash_a, count_a), (hash_b, count_b)| {
+                count_a.cmp(count_b).then_with(|| hash_b.cmp(hash_a))
+            })
+    }
+
+    /// Removes and returns orphans whose previous block is `prev_hash`.
+    pub fn remove_by_prev_hash(&mut self, prev_hash: &CryptoHash) -> Option<Vec<Orphan>> {
         let mut removed_hashes: HashSet<CryptoHash> = HashSet::default();
-        let ret = self.prev_hash_idx.remove(&prev_hash).map(|hs| {
+        let ret = self.prev_hash_idx.remove(prev_hash).map(|hs| {
             hs.iter()
                 .filter_map(|h| {
                     removed_hashes.insert(h.clone());
-                    self.orphans.remove(h)
+                    self.remove(h)
                 })
                 .collect()
         });
 
-        self.height_idx.retain(|_, ref mut xs| xs.iter().any(|x| !removed_hashes.contains(&x)));
+        self.height_idx.retain(|_, xs| {
+            xs.retain(|x| !removed_hashes.contains(x));
+            !xs.is_empty()
+        });
 
         ret
     }
@@ -206,6 +441,43 @@ pub struct Chain {
     pub block_economics_config: BlockEconomicsConfig,
     pub doomslug_threshold_mode: DoomslugThresholdMode,
     pending_states_to_patch: Option<Vec<StateRecord>>,
+    /// Archival nodes keep full history, so garbage collection is refused.
+    pub archival: bool,
+    /// Maximum number of blocks the head can be rolled back by a reorg. Unlimited if `None`.
+    pub max_reorg_depth: Option<BlockHeightDelta>,
+    /// If set, blocks this many heights below the head become finalized, see `finalized_head`,
+    /// and reorgs reverting the finalized head are rejected. Doomslug finality is unaffected.
+    pub finality_depth: Option<BlockHeightDelta>,
+    /// Blocks with timestamps further than this in the future are rejected.
+    pub acceptable_time_difference: Duration,
+    reorg_subscribers: Vec<mpsc::Sender<ReorgEvent>>,
+    event_handlers: Vec<Box<dyn ChainEventHandler>>,
+    /// Recently rejected blocks. Orphans built on top of them are dropped.
+    invalid_blocks: SizedCache<CryptoHash, ()>,
+    /// Headers that passed `process_block_header`, with their signatures. Their blocks skip the
+    /// signature and approval checks.
+    validated_headers: SizedCache<CryptoHash, Signature>,
+    on_orphan_evicted: Option<Box<dyn FnMut(CryptoHash, CryptoHash) + Send>>,
+    tx_observer: Option<TxObserver>,
+    on_finality_advanced: Option<Box<dyn FnMut(Tip, Vec<CryptoHash>) + Send>>,
+    /// When header sync last moved the header head forward.
+    header_head_advanced: Instant,
+    metrics: ChainMetrics,
+    /// Applying a shard's chunk slower than this is logged as a warning. Chunk application is
+    /// only timed while this is set.
+    pub apply_time_warn_threshold: Option<TimeDuration>,
+    /// While set, new blocks are queued in the orphan pool instead of being processed.
+    processing_paused: bool,
+    /// Trust all headers like the ones of blocks we produced and skip their timestamp checks.
+    /// Only set by tests through `adv_trust_headers`.
+    trust_headers: bool,
+    /// Time that block timestamps are checked against.
+    clock: Arc<dyn Clock>,
+    /// Equivocations found among accepted blocks, until taken by `take_equivocation_evidence`.
+    equivocation_evidence: Vec<EquivocationProof>,
+    /// Number of times the missing chunks of a block were requested again, and when that was
+    /// last done, for blocks in `blocks_with_missing_chunks`.
+    chunk_rerequests: HashMap<CryptoHash, (u32, Instant)>,
 }
 
 impl Chain {
@@ -215,6 +487,7 @@ impl Chain {
         doomslug_threshold_mode: DoomslugThresholdMode,
     ) -> Result<Chain, Error> {
         let (store, state_roots) = runtime_adapter.genesis_state();
+        check_genesis_state_roots(&state_roots, runtime_adapter.num_shards())?;
         let store = ChainStore::new(store, chain_genesis.height);
         let genesis_chunks = genesis_chunks(
             state_roots.clone(),
@@ -243,6 +516,25 @@ impl Chain {
             block_economics_config: BlockEconomicsConfig::from(chain_genesis),
             doomslug_threshold_mode,
             pending_states_to_patch: None,
+            archival: false,
+            max_reorg_depth: None,
+            finality_depth: None,
+            acceptable_time_difference: Duration::seconds(ACCEPTABLE_TIME_DIFFERENCE),
+            reorg_subscribers: vec![],
+            event_handlers: vec![],
+            invalid_blocks: SizedCache::with_size(INVALID_BLOCKS_CACHE_SIZE),
+            validated_headers: SizedCache::with_size(VALIDATED_HEADERS_CACHE_SIZE),
+            on_orphan_evicted: None,
+            tx_observer: None,
+            on_finality_advanced: None,
+            header_head_advanced: Instant::now(),
+            metrics: ChainMetrics::default(),
+            apply_time_warn_threshold: None,
+            processing_paused: false,
+            trust_headers: false,
+            clock: Arc::new(RealClock),
+            equivocation_evidence: vec![],
+            chunk_rerequests: HashMap::default(),
         })
     }
 
@@ -253,6 +545,7 @@ impl Chain {
     ) -> Result<Chain, Error> {
         // Get runtime initial state and create genesis block out of it.
         let (store, state_roots) = runtime_adapter.genesis_state();
+        check_genesis_state_roots(&state_roots, runtime_adapter.num_shards())?;
         let mut store = ChainStore::new(store, chain_genesis.height);
         let genesis_chunks = genesis_chunks(
             state_roots.clone(),
@@ -316,6 +609,18 @@ impl Chain {
                         &genesis.hash(),
                         BlockExtra { challenges_result: vec![] },
                     );
+                    for (shard_id, receipts) in chain_genesis.genesis_receipts.iter() {
+                        let shard_proof = ShardProof {
+                            from_shard_id: *shard_id,
+                            to_shard_id: *shard_id,
+                            proof: vec![],
+                        };
+                        store_update.save_incoming_receipt(
+                            &genesis.hash(),
+                            *shard_id,
+                            vec![ReceiptProof(receipts.clone(), shard_proof)],
+                        );
+                    }
 
                     for (chunk_header, state_root) in
                         genesis.chunks().iter().zip(state_roots.iter())
@@ -347,7 +652,7 @@ impl Chain {
 
         info!(target: "chain", "Init: head @ {} [{}]", head.height, head.last_block_hash);
 
-        Ok(Chain {
+        let mut chain = Chain {
             store,
             runtime_adapter,
             orphans: OrphanBlockPool::new(),
@@ -358,7 +663,134 @@ impl Chain {
             block_economics_config: BlockEconomicsConfig::from(chain_genesis),
             doomslug_threshold_mode,
             pending_states_to_patch: None,
-        })
+            archival: false,
+            max_reorg_depth: None,
+            finality_depth: None,
+            acceptable_time_difference: Duration::seconds(ACCEPTABLE_TIME_DIFFERENCE),
+            reorg_subscribers: vec![],
+            event_handlers: vec![],
+            invalid_blocks: SizedCache::with_size(INVALID_BLOCKS_CACHE_SIZE),
+            validated_headers: SizedCache::with_size(VALIDATED_HEADERS_CACHE_SIZE),
+            on_orphan_evicted: None,
+            tx_observer: None,
+            on_finality_advanced: None,
+            header_head_advanced: Instant::now(),
+            metrics: ChainMetrics::default(),
+            apply_time_warn_threshold: None,
+            processing_paused: false,
+            trust_headers: false,
+            clock: Arc::new(RealClock),
+            equivocation_evidence: vec![],
+            chunk_rerequests: HashMap::default(),
+        };
+        Ok(chain)
+    }
+
+    /// Puts orphans saved by `flush_orphans` back into the orphan pool with their provenance,
+    /// and removes them from the store. Orphans that are already known, or whose header is older
+    /// than `max_age_secs` of the pool config, are dropped. Call `check_restored_orphans`
+    /// afterwards to process the orphans whose previous block is known by now.
+    pub fn restore_orphans(&mut self) -> Result<(), Error> {
+        let orphans = self.store.iterate_orphans()?;
+        if orphans.is_empty() {
+            return Ok(());
+        }
+        let max_age = TimeDuration::from_secs(self.orphans.config.max_age_secs);
+        let now = self.clock.now();
+        let mut restored = vec![];
+        for (block, provenance) in orphans {
+            restored.push(*block.hash());
+            // The time the orphan was added isn't kept, so its age is counted from the header.
+            let age =
+                now.signed_duration_since(block.header().timestamp()).to_std().unwrap_or_default();
+            if age >= max_age || self.store.block_exists(block.hash())? {
+                continue;
+            }
+            let added = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+            self.add_orphan(Orphan::new(block, provenance, added));
+        }
+        debug!(target: "chain", "Restored {} orphans", self.orphans.len());
+        let mut chain_store_update = self.store.store_update();
+        for hash in restored {
+            chain_store_update.remove_orphan(hash);
+        }
+        chain_store_update.commit()
+    }
+
+    /// Saves blocks from the orphan pool, so they are restored by `restore_orphans` on the next
+    /// start. Orphans saved earlier that have left the pool since are removed.
+    pub fn flush_orphans(&mut self) -> Result<(), Error> {
+        let saved = self.store.iterate_orphans()?;
+        let mut chain_store_update = self.store.store_update();
+        for (block, _) in saved {
+            chain_store_update.remove_orphan(*block.hash());
+        }
+        for orphan in self.orphans.orphans.values() {
+            chain_store_update.add_orphan(orphan.block.clone(), orphan.provenance.clone());
+        }
+        chain_store_update.commit()
+    }
+
+    /// Pauses block processing, e.g. while `set_shard_state` downloads the state blocks would be
+    /// applied on. While paused, `process_block` admits every block to the orphan pool with the
+    /// checks an orphan gets, even if its previous block is known, and returns
+    /// `ErrorKind::Orphan`; `stage_block` and `process_blocks_batch` fail with
+    /// `ErrorKind::Orphan` without pooling. Blocks waiting in `blocks_with_missing_chunks` stay
+    /// there, and the ones released by arriving chunks are queued the same way.
+    pub fn pause_processing(&mut self) {
+        self.processing_paused = true;
+    }
+
+    /// Resumes block processing and replays the blocks queued while it was paused. Returns the
+    /// new head if it changed.
+    pub fn resume_processing<F, F2, F3>(
+        &mut self,
+        me: &Option<AccountId>,
+        block_accepted: F,
+        block_misses_chunks: F2,
+        on_challenge: F3,
+    ) -> Result<Option<Tip>, Error>
+    where
+        F: Copy + FnMut(AcceptedBlock) -> (),
+        F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
+        F3: Copy + FnMut(ChallengeBody) -> (),
+    {
+        self.processing_paused = false;
+        self.check_restored_orphans(me, block_accepted, block_misses_chunks, on_challenge)
+    }
+
+    pub fn is_processing_paused(&self) -> bool {
+        self.processing_paused
+    }
+
+    /// Processes restored orphans whose previous blocks became known while the node was down.
+    pub fn check_restored_orphans<F, F2, F3>(
+        &mut self,
+        me: &Option<AccountId>,
+        block_accepted: F,
+        block_misses_chunks: F2,
+        on_challenge: F3,
+    ) -> Result<Option<Tip>, Error>
+    where
+        F: Copy + FnMut(AcceptedBlock) -> (),
+        F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
+        F3: Copy + FnMut(ChallengeBody) -> (),
+    {
+        let mut known_prev_hashes = vec![];
+        for prev_hash in self.orphans.prev_hash_idx.keys() {
+            if self.store.block_exists(prev_hash)? {
+                known_prev_hashes.push(*prev_hash);
+            }
+        }
+        let mut new_head = None;
+        for prev_hash in known_prev_hashes {
+            if let Some(tip) =
+                self.check_orphans(me, prev_hash, block_accepted, block_misses_chunks, on_challenge)
+            {
+                new_head = Some(tip);
+            }
+        }
+        Ok(new_head)
     }
 
     #[cfg(feature = "adversarial")]
@@ -366,6 +798,19 @@ impl Chain {
         self.doomslug_threshold_mode = DoomslugThresholdMode::NoApprovals
     }
 
+    /// Replaces the clock that block timestamps are checked against, e.g. with a `MockClock` in
+    /// tests.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Makes header validation skip the approvals, finality info and timestamp checks for all
+    /// blocks, so that tests can inject blocks with synthetic headers.
+    #[cfg(feature = "adversarial")]
+    pub fn adv_trust_headers(&mut self, trust_headers: bool) {
+        self.trust_headers = trust_headers;
+    }
+
     pub fn compute_collection_hash<T: BorshSerialize>(elems: Vec<T>) -> Result<CryptoHash, Error> {
         Ok(hash(&elems.try_to_vec()?))
     }
@@ -462,11 +907,67 @@ impl Chain {
             byzantine_assert!(false);
             return Err(e.into());
         }
-        self.orphans.add(Orphan {
-            block: block.clone(),
-            provenance: Provenance::NONE,
-            added: Instant::now(),
-        });
+        self.add_orphan(Orphan::new(block.clone(), Provenance::NONE, Instant::now()));
+        Ok(())
+    }
+
+    fn add_orphan(&mut self, orphan: Orphan) {
+        let evicted = self.orphans.add(orphan);
+        if let Some(on_orphan_evicted) = self.on_orphan_evicted.as_mut() {
+            for (hash, prev_hash) in evicted {
+                on_orphan_evicted(hash, prev_hash);
+            }
+        }
+    }
+
+    /// Sets a callback invoked with every transaction of a new chunk, together with its hash,
+    /// right before the chunk is applied. The callback can't affect processing of the block.
+    pub fn set_tx_observer<F>(&mut self, tx_observer: F)
+    where
+        F: FnMut(&CryptoHash, &SignedTransaction) + Send + 'static,
+    {
+        self.tx_observer = Some(Box::new(tx_observer));
+    }
+
+    /// Sets a callback invoked with (hash, prev_hash) of every orphan evicted from a full
+    /// orphan pool, so that the caller can request the missing parent again.
+    pub fn set_on_orphan_evicted<F>(&mut self, on_orphan_evicted: F)
+    where
+        F: FnMut(CryptoHash, CryptoHash) + Send + 'static,
+    {
+        self.on_orphan_evicted = Some(Box::new(on_orphan_evicted));
+    }
+
+    /// Sets a callback invoked with the new final head and the hashes of all blocks that became
+    /// final, in increasing order of height, whenever processing a block moves the final head.
+    pub fn set_on_finality_advanced<F>(&mut self, on_finality_advanced: F)
+    where
+        F: FnMut(Tip, Vec<CryptoHash>) + Send + 'static,
+    {
+        self.on_finality_advanced = Some(Box::new(on_finality_advanced));
+    }
+
+    /// Notifies the finality callback if the final head moved past `prev_final_head`.
+    fn process_finality(&mut self, prev_final_head: &Tip) -> Result<(), Error> {
+        if self.on_finality_advanced.is_none() {
+            return Ok(());
+        }
+        let final_head = self.final_head()?;
+        if final_head.height <= prev_final_head.height {
+            return Ok(());
+        }
+        let mut newly_finalized = vec![];
+        for header in self.ancestors(&final_head.last_block_hash) {
+            let header = header?;
+            if header.height() <= prev_final_head.height {
+                break;
+            }
+            newly_finalized.push(*header.hash());
+        }
+        newly_finalized.reverse();
+        if let Some(on_finality_advanced) = self.on_finality_advanced.as_mut() {
+            on_finality_advanced(final_head, newly_finalized);
+        }
         Ok(())
     }
 
@@ -562,6 +1063,85 @@ impl Chain {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("GC".into());
 
+        self.check_pruning_allowed()?;
+        self.clear_blocks(tries, gc_blocks_limit, true, &mut GcReport::default())
+    }
+
+    /// Runs a full garbage collection pass: drops stale orphans, then fork blocks, then
+    /// canonical blocks below the gc stop height. Refused on archival nodes.
+    pub fn run_gc(&mut self, policy: GcPolicy) -> Result<GcReport, Error> {
+        #[cfg(feature = "delay_detector")]
+        let _d = DelayDetector::new("GC".into());
+
+        self.check_pruning_allowed()?;
+        let tries = self.runtime_adapter.get_tries();
+        let mut report = GcReport::default();
+        if let Some(max_orphan_age) = policy.max_orphan_age {
+            report.orphans = self.orphans.prune_older_than(max_orphan_age);
+        }
+        self.clear_blocks(tries, policy.gc_blocks_limit, policy.clear_forks, &mut report)?;
+        Ok(report)
+    }
+
+    /// Deletes bodies of blocks below `below_height` and of chunks included in them, keeping
+    /// block headers, so the header chain can still be validated. Blocks that a reorg could
+    /// revert are kept: those higher than `max_reorg_depth` below the head or, if it's not
+    /// set, higher than the final head. Returns the number of removed blocks and chunks.
+    pub fn prune_blocks(&mut self, below_height: BlockHeight) -> Result<(NumBlocks, u64), Error> {
+        self.check_pruning_allowed()?;
+        let head = self.store.head()?;
+        let reorg_limit = match self.max_reorg_depth {
+            Some(max_reorg_depth) => head.height.saturating_sub(max_reorg_depth),
+            None => self.store.final_head()?.height,
+        };
+        // Genesis is kept in any case.
+        let from_height = std::cmp::max(self.store.tail()?, self.genesis.header().height() + 1);
+        let to_height = std::cmp::min(below_height, reorg_limit);
+        let mut removed_blocks = 0;
+        let mut removed_chunks = 0;
+        for height in from_height..to_height {
+            let block_hashes = match self.store.get_all_block_hashes_by_height(height) {
+                Ok(block_hashes) => block_hashes.values().flatten().cloned().collect::<Vec<_>>(),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            };
+            let mut chain_store_update = self.store.store_update();
+            // Blocks on different forks may include the same chunk.
+            let mut deleted_chunks = HashSet::new();
+            for block_hash in block_hashes {
+                let block = match chain_store_update.get_block(&block_hash) {
+                    Ok(block) => block.clone(),
+                    Err(_) => continue,
+                };
+                for chunk_header in block.chunks().iter() {
+                    let chunk_hash = chunk_header.chunk_hash();
+                    if chunk_header.height_included() != height
+                        || deleted_chunks.contains(&chunk_hash)
+                        || chain_store_update.get_chunk(&chunk_hash).is_err()
+                    {
+                        continue;
+                    }
+                    chain_store_update.delete_chunk_body(&chunk_hash)?;
+                    deleted_chunks.insert(chunk_hash);
+                    removed_chunks += 1;
+                }
+                chain_store_update.delete_block_body(&block_hash);
+                removed_blocks += 1;
+            }
+            chain_store_update.commit()?;
+        }
+        Ok((removed_blocks, removed_chunks))
+    }
+
+    fn clear_blocks(
+        &mut self,
+        tries: ShardTries,
+        gc_blocks_limit: NumBlocks,
+        clear_forks: bool,
+        report: &mut GcReport,
+    ) -> Result<(), Error> {
         let head = self.store.head()?;
         let tail = self.store.tail()?;
         let gc_stop_height = self.runtime_adapter.get_gc_stop_height(&head.last_block_hash);
@@ -587,15 +1167,19 @@ impl Chain {
         let mut gc_blocks_remaining = gc_blocks_limit;
 
         // Forks Cleaning
-        let stop_height = std::cmp::max(tail, fork_tail.saturating_sub(GC_FORK_CLEAN_STEP));
-        for height in (stop_height..fork_tail).rev() {
-            self.clear_forks_data(tries.clone(), height, &mut gc_blocks_remaining)?;
-            if gc_blocks_remaining == 0 {
-                return Ok(());
+        if clear_forks {
+            let stop_height = std::cmp::max(tail, fork_tail.saturating_sub(GC_FORK_CLEAN_STEP));
+            for height in (stop_height..fork_tail).rev() {
+                let gc_blocks_before = gc_blocks_remaining;
+                self.clear_forks_data(tries.clone(), height, &mut gc_blocks_remaining)?;
+                report.fork_blocks += gc_blocks_before - gc_blocks_remaining;
+                if gc_blocks_remaining == 0 {
+                    return Ok(());
+                }
+                let mut chain_store_update = self.store.store_update();
+                chain_store_update.update_fork_tail(height);
+                chain_store_update.commit()?;
             }
-            let mut chain_store_update = self.store.store_update();
-            chain_store_update.update_fork_tail(height);
-            chain_store_update.commit()?;
         }
 
         // Canonical Chain Clearing
@@ -620,6 +1204,7 @@ impl Chain {
                         chain_store_update
                             .clear_block_data(*block_hash, GCMode::Canonical(tries.clone()))?;
                         gc_blocks_remaining -= 1;
+                        report.canonical_blocks += 1;
                     } else {
                         return Err(ErrorKind::GCError(
                             "block on canonical chain shouldn't have refcount 0".into(),
@@ -634,12 +1219,20 @@ impl Chain {
         Ok(())
     }
 
+    fn check_pruning_allowed(&self) -> Result<(), Error> {
+        if self.archival {
+            return Err(ErrorKind::Other("archival node: pruning disabled".to_string()).into());
+        }
+        Ok(())
+    }
+
     pub fn clear_forks_data(
         &mut self,
         tries: ShardTries,
         height: BlockHeight,
         gc_blocks_remaining: &mut NumBlocks,
     ) -> Result<(), Error> {
+        self.check_pruning_allowed()?;
         if let Ok(blocks_current_height) = self.store.get_all_block_hashes_by_height(height) {
             let blocks_current_height =
                 blocks_current_height.values().flatten().cloned().collect::<Vec<_>>();
@@ -720,9 +1313,23 @@ impl Chain {
         // We create new chain update, but it's not going to be committed so it's read only.
         let mut chain_update = self.chain_update();
         chain_update.process_block_header(header, on_challenge)?;
+        self.validated_headers.cache_set(*header.hash(), header.signature().clone());
         Ok(())
     }
 
+    /// Checks whether `process_block` would accept the block, short of applying its chunks.
+    /// Nothing is stored and no challenges are reported.
+    pub fn validate_block(
+        &mut self,
+        me: &Option<AccountId>,
+        block: &Block,
+        provenance: &Provenance,
+    ) -> Result<(), Error> {
+        // We create new chain update, but it's not going to be committed so it's read only.
+        let mut chain_update = self.chain_update();
+        chain_update.validate_block(me, block, provenance)
+    }
+
     pub fn mark_block_as_challenged(
         &mut self,
         block_hash: &CryptoHash,
@@ -745,6 +1352,55 @@ impl Chain {
         block_misses_chunks: F2,
         on_challenge: F3,
     ) -> Result<Option<Tip>, Error>
+    where
+        F: Copy + FnMut(AcceptedBlock) -> (),
+        F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
+        F3: Copy + FnMut(ChallengeBody) -> (),
+    {
+        self.process_block_with_result(
+            me,
+            block,
+            provenance,
+            block_accepted,
+            block_misses_chunks,
+            on_challenge,
+        )
+        .map(|result| result.new_head)
+    }
+
+    /// Same as `process_block`, but returns the notifications instead of passing them to
+    /// callbacks, so they can be handled after the chain is released. If processing fails, the
+    /// notifications recorded so far are dropped; the error itself tells why, e.g.
+    /// `ChunksMissing` lists the missing chunks.
+    pub fn process_block_collect(
+        &mut self,
+        me: &Option<AccountId>,
+        block: Block,
+        provenance: Provenance,
+    ) -> Result<(Option<Tip>, Vec<ChainEvent>), Error> {
+        let events = RefCell::new(vec![]);
+        let new_head = self.process_block(
+            me,
+            block,
+            provenance,
+            |accepted| events.borrow_mut().push(ChainEvent::BlockAccepted(accepted)),
+            |chunks| events.borrow_mut().push(ChainEvent::ChunksMissing(chunks)),
+            |challenge| events.borrow_mut().push(ChainEvent::Challenge(challenge)),
+        )?;
+        Ok((new_head, events.into_inner()))
+    }
+
+    /// Same as `process_block`, but also tells the status of the block and how many orphans
+    /// were accepted after it.
+    pub fn process_block_with_result<F, F2, F3>(
+        &mut self,
+        me: &Option<AccountId>,
+        block: Block,
+        provenance: Provenance,
+        block_accepted: F,
+        block_misses_chunks: F2,
+        on_challenge: F3,
+    ) -> Result<ProcessBlockResult, Error>
     where
         F: Copy + FnMut(AcceptedBlock) -> (),
         F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
@@ -761,26 +1417,129 @@ impl Chain {
             on_challenge,
         );
         near_metrics::stop_timer(timer);
-        if res.is_ok() {
-            near_metrics::inc_counter(&metrics::BLOCK_PROCESSED_SUCCESSFULLY_TOTAL);
+        let (mut new_head, status) = res?;
+        near_metrics::inc_counter(&metrics::BLOCK_PROCESSED_SUCCESSFULLY_TOTAL);
 
-            if let Some(new_res) = self.check_orphans(
-                me,
-                block_hash,
-                block_accepted,
-                block_misses_chunks,
-                on_challenge,
-            ) {
-                return Ok(Some(new_res));
-            }
+        let (orphans_head, orphans_unlocked) =
+            self.unlock_orphans(me, block_hash, block_accepted, block_misses_chunks, on_challenge);
+        if orphans_head.is_some() {
+            new_head = orphans_head;
         }
-        res
+        Ok(ProcessBlockResult { new_head, status, orphans_unlocked })
     }
 
-    /// Process challenge to invalidate chain. This is done between blocks to unroll the chain as
-    /// soon as possible and allow next block producer to skip invalid blocks.
-    pub fn process_challenge(&mut self, challenge: &Challenge) {
-        let head = unwrap_or_return!(self.head());
+    /// Processes a block without committing the resulting store changes.
+    /// The caller can merge its own changes into the returned `StagedBlock` and commit
+    /// them together with the block. Orphans and blocks with missing chunks are not
+    /// pooled, and orphans depending on the block are not processed.
+    pub fn stage_block(
+        &mut self,
+        me: &Option<AccountId>,
+        block: &Block,
+        provenance: &Provenance,
+    ) -> Result<StagedBlock, Error> {
+        if self.processing_paused {
+            return Err(ErrorKind::Orphan.into());
+        }
+        let prev_head = self.store.head()?;
+        let mut chain_update = self.chain_update();
+        let (head, needs_to_start_fetching_state) =
+            chain_update.process_block(me, block, provenance, |_| {})?;
+        chain_update.chain_store_update.save_block_height_processed(block.header().height());
+        let status = Self::determine_status(head.clone(), prev_head);
+        Ok(StagedBlock {
+            chain_store_update: chain_update.chain_store_update,
+            head,
+            status,
+            needs_to_start_fetching_state,
+        })
+    }
+
+    /// Processes a contiguous run of blocks and commits all their changes at once.
+    /// If any block fails, nothing is committed and the index of the failing block is returned
+    /// along with the error, so the caller can retry from there. Failures of the batch as a
+    /// whole, e.g. of the final commit, come without an index. Orphans and blocks with
+    /// missing chunks fail the batch instead of being pooled.
+    /// Runtimes that read state from the store don't see state changes of the batch until it
+    /// is committed, so the batch must not apply chunks on top of each other there.
+    pub fn process_blocks_batch<F, F2, F3>(
+        &mut self,
+        me: &Option<AccountId>,
+        blocks: Vec<(Block, Provenance)>,
+        mut block_accepted: F,
+        block_misses_chunks: F2,
+        on_challenge: F3,
+    ) -> Result<Option<Tip>, (Option<usize>, Error)>
+    where
+        F: Copy + FnMut(AcceptedBlock) -> (),
+        F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
+        F3: Copy + FnMut(ChallengeBody) -> (),
+    {
+        if self.processing_paused {
+            return Err((None, ErrorKind::Orphan.into()));
+        }
+        let mut head = None;
+        let mut accepted = vec![];
+        let mut blocks_to_download_state = vec![];
+        let prev_final_head = self.final_head().map_err(|e| (None, e))?;
+        let mut chain_update = self.chain_update();
+        for (i, (block, provenance)) in blocks.iter().enumerate() {
+            let prev_head = chain_update.chain_store_update.head().map_err(|e| (Some(i), e))?;
+            let (block_head, needs_to_start_fetching_state) = chain_update
+                .process_block(me, block, provenance, on_challenge)
+                .map_err(|e| (Some(i), e))?;
+            chain_update.chain_store_update.save_block_height_processed(block.header().height());
+            let status = Self::determine_status(block_head.clone(), prev_head);
+            if needs_to_start_fetching_state {
+                blocks_to_download_state.push(i);
+            }
+            if block_head.is_some() {
+                head = block_head;
+            }
+            accepted.push((*block.hash(), status, provenance.clone()));
+        }
+        let apply_times = chain_update.apply_times.take();
+        chain_update.commit().map_err(|e| (None, e))?;
+        self.record_apply_times(apply_times);
+
+        self.pending_states_to_patch = None;
+        for i in blocks_to_download_state {
+            debug!(target: "chain", "Downloading state for block {}", blocks[i].0.hash());
+            self.start_downloading_state(me, &blocks[i].0).map_err(|e| (Some(i), e))?;
+        }
+        if let Err(e) = self.process_finality(&prev_final_head) {
+            warn!(target: "chain", "Failed to compute newly finalized blocks: {}", e);
+        }
+        for (block_hash, status, provenance) in accepted {
+            self.metrics.record_accepted(&status);
+            let reorg = match self.process_reorg(&status, &block_hash) {
+                Ok(reorg) => reorg,
+                Err(e) => {
+                    warn!(target: "chain", "Failed to compute reorg to {}: {}", block_hash, e);
+                    None
+                }
+            };
+            self.record_equivocation(&block_hash);
+            let accepted = AcceptedBlock { hash: block_hash, status, provenance, reorg };
+            self.notify_block_accepted(&accepted);
+            block_accepted(accepted);
+            if let Some(tip) = self.check_orphans(
+                me,
+                block_hash,
+                block_accepted,
+                block_misses_chunks,
+                on_challenge,
+            ) {
+                head = Some(tip);
+            }
+        }
+        Ok(head)
+    }
+
+    /// Process challenge to invalidate chain. This is done between blocks to unroll the chain as
+    /// soon as possible and allow next block producer to skip invalid blocks.
+    pub fn process_challenge(&mut self, challenge: &Challenge) {
+        let head = unwrap_or_return!(self.head());
         let mut chain_update = self.chain_update();
         match chain_update.verify_challenges(
             &vec![challenge.clone()],
@@ -796,10 +1555,68 @@ impl Chain {
         unwrap_or_return!(chain_update.commit());
     }
 
+    /// Verifies that `headers` form a chain on top of `trusted` without touching the store:
+    /// each header must point to the previous one, have a larger height, a later timestamp and
+    /// finality info that follows from its previous header. Signatures and approvals are not
+    /// checked since they need the epoch info.
+    pub fn verify_header_segment(
+        &self,
+        trusted: &BlockHeader,
+        headers: &[BlockHeader],
+    ) -> Result<(), Error> {
+        let mut prev_header = trusted;
+        for header in headers {
+            if header.prev_hash() != prev_header.hash() {
+                return Err(
+                    ErrorKind::InvalidHeaderChain(*prev_header.hash(), *header.hash()).into()
+                );
+            }
+            if header.height() <= prev_header.height() {
+                return Err(ErrorKind::InvalidBlockHeight(header.height()).into());
+            }
+            ChainUpdate::check_header_progression(header, prev_header)?;
+            ChainUpdate::check_finality_info(header, prev_header)?;
+            prev_header = header;
+        }
+        Ok(())
+    }
+
     /// Processes headers and adds them to store for syncing.
     pub fn sync_block_headers<F>(
+        &mut self,
+        headers: Vec<BlockHeader>,
+        on_challenge: F,
+    ) -> Result<(), Error>
+    where
+        F: Copy + FnMut(ChallengeBody) -> (),
+    {
+        self.sync_block_headers_impl(headers, &HashSet::new(), on_challenge)
+    }
+
+    /// Same as `sync_block_headers`, but verifies header signatures in parallel beforehand.
+    /// Signatures that can't be verified up front (e.g. because the previous headers are not
+    /// processed yet) are verified sequentially as usual, in height order.
+    pub fn bulk_process_headers_with_validation_cache<F>(
+        &mut self,
+        headers: Vec<BlockHeader>,
+        on_challenge: F,
+    ) -> Result<(), Error>
+    where
+        F: Copy + FnMut(ChallengeBody) -> (),
+    {
+        let runtime_adapter = self.runtime_adapter.clone();
+        let verified_signatures = headers
+            .par_iter()
+            .filter(|header| runtime_adapter.verify_header_signature(header).unwrap_or(false))
+            .map(|header| *header.hash())
+            .collect::<HashSet<_>>();
+        self.sync_block_headers_impl(headers, &verified_signatures, on_challenge)
+    }
+
+    fn sync_block_headers_impl<F>(
         &mut self,
         mut headers: Vec<BlockHeader>,
+        verified_signatures: &HashSet<CryptoHash>,
         on_challenge: F,
     ) -> Result<(), Error>
     where
@@ -814,6 +1631,13 @@ impl Chain {
             return Ok(());
         };
 
+        // Headers must link up, only the first one may follow a header we already have.
+        for pair in headers.windows(2) {
+            if pair[1].prev_hash() != pair[0].hash() {
+                return Err(ErrorKind::InvalidHeaderChain(*pair[0].hash(), *pair[1].hash()).into());
+            }
+        }
+
         let all_known = if let Some(last_header) = headers.last() {
             self.store.get_block_header(&last_header.hash()).is_ok()
         } else {
@@ -833,7 +1657,13 @@ impl Chain {
                     },
                 }
 
-                chain_update.validate_header(header, &Provenance::SYNC, on_challenge)?;
+                chain_update.validate_header_impl(
+                    header,
+                    &Provenance::SYNC,
+                    verified_signatures.contains(header.hash()),
+                    false,
+                    on_challenge,
+                )?;
                 chain_update.chain_store_update.save_block_header(header.clone())?;
 
                 // Add validator proposals for given header.
@@ -847,6 +1677,7 @@ impl Chain {
             }
         }
 
+        let prev_header_head_height = self.header_head()?.height;
         let mut chain_update = self.chain_update();
 
         if let Some(header) = headers.last() {
@@ -854,7 +1685,17 @@ impl Chain {
             chain_update.update_header_head_if_not_challenged(header)?;
         }
 
-        chain_update.commit()
+        chain_update.commit()?;
+        if self.header_head()?.height > prev_header_head_height {
+            self.header_head_advanced = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Whether header sync hasn't moved the header head forward for more than `threshold`
+    /// as of `since`, in which case syncing from a different peer may help.
+    pub fn sync_head_stalled(&self, since: Instant, threshold: TimeDuration) -> bool {
+        since.saturating_duration_since(self.header_head_advanced) > threshold
     }
 
     /// Returns if given block header is on the current chain.
@@ -867,10 +1708,79 @@ impl Chain {
         }
     }
 
-    /// Finds first of the given hashes that is known on the main chain.
+    /// Moves the head back to the given block of the current chain. Canonical height index
+    /// entries above it are removed; the header head is left as is. Refuses to rewind below
+    /// the final head.
+    pub fn rewind_to(&mut self, hash: &CryptoHash) -> Result<(), Error> {
+        let head = self.head()?;
+        let header = self.get_block_header(hash)?.clone();
+        if header.height() > head.height {
+            return Err(ErrorKind::Other(format!("{} is above the head", hash)).into());
+        }
+        self.is_on_current_chain(&header)?;
+        // Neither the doomslug final head nor the head finalized by `finality_depth` may be
+        // reverted.
+        let mut final_height = self.final_head()?.height;
+        match self.finalized_head() {
+            Ok(finalized_head) => final_height = final_height.max(finalized_head.height),
+            Err(e) => match e.kind() {
+                ErrorKind::DBNotFoundErr(_) => {}
+                _ => return Err(e),
+            },
+        }
+        if header.height() < final_height {
+            return Err(ErrorKind::Other(format!(
+                "Cannot rewind to {} at {} below the final head at {}",
+                hash,
+                header.height(),
+                final_height
+            ))
+            .into());
+        }
+        // The new head must have its body.
+        self.get_block(hash)?;
+
+        let mut store_update = self.store.store_update();
+        store_update.save_body_head(&Tip::from_header(&header))?;
+        store_update.clear_block_hashes_by_height(header.height() + 1..=head.height);
+        store_update.commit()?;
+        info!(target: "chain", "Rewound head from {} at {} to {} at {}", head.last_block_hash, head.height, hash, header.height());
+        Ok(())
+    }
+
+    /// Returns hashes of the header chain going back from the header head with steps of 1, 2, 4,
+    /// ... heights down to genesis, for a peer to find the fork point with `find_common_header`.
+    /// Heights skipped on the chain are left out, so the header head and genesis are always the
+    /// first and the last hash.
+    pub fn get_locator(&mut self) -> Result<Vec<CryptoHash>, Error> {
+        let header_head = self.header_head()?;
+        let genesis_height = self.genesis.header().height();
+        let mut locator = vec![header_head.last_block_hash];
+        let mut height = header_head.height;
+        let mut step = 1;
+        while height > genesis_height {
+            height = height.saturating_sub(step).max(genesis_height);
+            step = step.saturating_mul(2);
+            match self.store.get_block_hash_by_height(height) {
+                Ok(hash) => locator.push(hash),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => {}
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(locator)
+    }
+
+    /// Finds first of the given hashes that is known on the main chain. Gives up after looking
+    /// at `MAX_COMMON_HEADER_LOOKUPS` hashes.
     pub fn find_common_header(&mut self, hashes: &[CryptoHash]) -> Option<BlockHeader> {
-        for hash in hashes {
+        let genesis_height = self.genesis.header().height();
+        for hash in hashes.iter().take(MAX_COMMON_HEADER_LOOKUPS) {
             if let Ok(header) = self.get_block_header(&hash).map(|h| h.clone()) {
+                if header.height() < genesis_height {
+                    continue;
+                }
                 if let Ok(header_at_height) = self.get_header_by_height(header.height()) {
                     if header.hash() == header_at_height.hash() {
                         return Some(header);
@@ -881,7 +1791,7 @@ impl Chain {
         None
     }
 
-    fn determine_status(&self, head: Option<Tip>, prev_head: Tip) -> BlockStatus {
+    fn determine_status(head: Option<Tip>, prev_head: Tip) -> BlockStatus {
         let has_head = head.is_some();
         let mut is_next_block = false;
 
@@ -903,6 +1813,79 @@ impl Chain {
         }
     }
 
+    /// Returns a receiver of all future reorgs of the chain head.
+    pub fn subscribe_reorgs(&mut self) -> mpsc::Receiver<ReorgEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.reorg_subscribers.push(sender);
+        receiver
+    }
+
+    /// Registers a handler notified of accepted blocks, missing chunks and reorgs, in addition to
+    /// the callbacks passed to `process_block`.
+    pub fn register_handler(&mut self, handler: Box<dyn ChainEventHandler>) {
+        self.event_handlers.push(handler);
+    }
+
+    fn notify_block_accepted(&mut self, block: &AcceptedBlock) {
+        for handler in self.event_handlers.iter_mut() {
+            handler.on_block_accepted(block);
+        }
+    }
+
+    /// Walks back from the old and the new head to their common ancestor.
+    fn reorg_event(
+        &mut self,
+        old_head: &CryptoHash,
+        new_head: &CryptoHash,
+    ) -> Result<ReorgEvent, Error> {
+        let mut old_header = self.get_block_header(old_head)?.clone();
+        let mut new_header = self.get_block_header(new_head)?.clone();
+        let mut reverted = vec![];
+        let mut applied = vec![];
+        while old_header.hash() != new_header.hash() {
+            if old_header.height() >= new_header.height() {
+                reverted.push(*old_header.hash());
+                old_header = self.get_previous_header(&old_header)?.clone();
+            } else {
+                applied.push(*new_header.hash());
+                new_header = self.get_previous_header(&new_header)?.clone();
+            }
+        }
+        applied.reverse();
+        Ok(ReorgEvent { common_ancestor: *old_header.hash(), reverted, applied })
+    }
+
+    /// Returns the path from the block where the fork of `hash` branches off the canonical chain
+    /// to `hash`, both included. If `hash` is on the canonical chain, the path is just `hash`.
+    /// Fails if the headers of `hash` and its fork are not known.
+    pub fn path_from_head_to(&mut self, hash: &CryptoHash) -> Result<Vec<CryptoHash>, Error> {
+        let head = self.head()?.last_block_hash;
+        let event = self.reorg_event(&head, hash)?;
+        let mut path = vec![event.common_ancestor];
+        path.extend(event.applied);
+        Ok(path)
+    }
+
+    /// Computes the reorg described by `status` of the new head `block_hash` and sends it to
+    /// reorg subscribers.
+    fn process_reorg(
+        &mut self,
+        status: &BlockStatus,
+        block_hash: &CryptoHash,
+    ) -> Result<Option<ReorgEvent>, Error> {
+        let old_head = match status {
+            BlockStatus::Reorg(old_head) => old_head,
+            _ => return Ok(None),
+        };
+        let event = self.reorg_event(old_head, block_hash)?;
+        // Drop subscribers that are no longer listening.
+        self.reorg_subscribers.retain(|sender| sender.send(event.clone()).is_ok());
+        for handler in self.event_handlers.iter_mut() {
+            handler.on_reorg(&event);
+        }
+        Ok(Some(event))
+    }
+
     pub fn reset_data_pre_state_sync(&mut self, sync_hash: CryptoHash) -> Result<(), Error> {
         let head = self.head()?;
         // Get header we were syncing into.
@@ -1042,7 +2025,7 @@ impl Chain {
         mut block_accepted: F,
         mut block_misses_chunks: F2,
         on_challenge: F3,
-    ) -> Result<Option<Tip>, Error>
+    ) -> Result<(Option<Tip>, BlockStatus), Error>
     where
         F: FnMut(AcceptedBlock) -> (),
         F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
@@ -1050,15 +2033,28 @@ impl Chain {
     {
         near_metrics::inc_counter(&metrics::BLOCK_PROCESSED_TOTAL);
 
+        // Genesis has no previous block, so it can't go through the regular checks.
+        if block.hash() == self.genesis.hash() {
+            return Err(ErrorKind::Unfit("genesis reprocess".to_string()).into());
+        }
+
         let prev_head = self.store.head()?;
+        let prev_final_head = self.store.final_head()?;
+        // The signature is not covered by the hash, so it has to match the validated one.
+        let header_validated = self.validated_headers.cache_remove(block.hash()).as_ref()
+            == Some(block.header().signature());
         let mut chain_update = self.chain_update();
+        chain_update.header_validated = header_validated;
+        chain_update.processing_paused = self.processing_paused;
         let maybe_new_head = chain_update.process_block(me, &block, &provenance, on_challenge);
         let block_height = block.header().height();
 
         match maybe_new_head {
             Ok((head, needs_to_start_fetching_state)) => {
                 chain_update.chain_store_update.save_block_height_processed(block_height);
+                let apply_times = chain_update.apply_times.take();
                 chain_update.commit()?;
+                self.record_apply_times(apply_times);
 
                 self.pending_states_to_patch = None;
 
@@ -1093,23 +2089,55 @@ impl Chain {
                     .sum::<i64>();
                 near_metrics::set_gauge(&metrics::VALIDATOR_AMOUNT_STAKED, sum);
 
-                let status = self.determine_status(head.clone(), prev_head);
+                let status = Self::determine_status(head.clone(), prev_head);
+                self.metrics.record_accepted(&status);
+                // The block is committed already, so failing to describe the reorg or the
+                // finality change must not keep it from being announced.
+                let reorg = match self.process_reorg(&status, block.hash()) {
+                    Ok(reorg) => reorg,
+                    Err(e) => {
+                        warn!(target: "chain", "Failed to compute reorg to {}: {}", block.hash(), e);
+                        None
+                    }
+                };
+                if let Err(e) = self.process_finality(&prev_final_head) {
+                    warn!(target: "chain", "Failed to compute newly finalized blocks: {}", e);
+                }
+                self.record_equivocation(block.hash());
 
                 // Notify other parts of the system of the update.
-                block_accepted(AcceptedBlock { hash: *block.hash(), status, provenance });
+                let accepted = AcceptedBlock {
+                    hash: *block.hash(),
+                    status: status.clone(),
+                    provenance,
+                    reorg,
+                };
+                self.notify_block_accepted(&accepted);
+                block_accepted(accepted);
 
-                Ok(head)
+                Ok((head, status))
             }
             Err(e) => {
                 match e.kind() {
                     ErrorKind::Orphan => {
                         let tail_height = self.store.tail()?;
-                        // we only add blocks that couldn't have been gc'ed to the orphan pool.
-                        if block_height >= tail_height {
+                        let prev_hash = *block.header().prev_hash();
+                        if self.invalid_blocks.cache_get(&prev_hash).is_some() {
+                            // Descendants of a rejected block can never be applied.
+                            debug!(
+                                target: "chain",
+                                "Process block: dropping orphan {:?} of invalid block {:?}",
+                                block.hash(),
+                                prev_hash,
+                            );
+                            self.invalid_blocks.cache_set(*block.hash(), ());
+                        } else if block_height >= tail_height {
+                            // we only add blocks that couldn't have been gc'ed to the orphan pool.
                             let block_hash = *block.hash();
-                            let orphan = Orphan { block, provenance, added: Instant::now() };
+                            let orphan = Orphan::new(block, provenance, Instant::now());
 
-                            self.orphans.add(orphan);
+                            self.add_orphan(orphan);
+                            self.metrics.orphans_added += 1;
 
                             debug!(
                                 target: "chain",
@@ -1126,13 +2154,17 @@ impl Chain {
                     }
                     ErrorKind::ChunksMissing(missing_chunks) => {
                         let block_hash = *block.hash();
+                        for handler in self.event_handlers.iter_mut() {
+                            handler.on_chunks_missing(&missing_chunks);
+                        }
                         block_misses_chunks(missing_chunks.clone());
-                        let orphan = Orphan { block, provenance, added: Instant::now() };
+                        let orphan = Orphan::new(block, provenance, Instant::now());
 
                         self.blocks_with_missing_chunks.add_block_with_missing_chunks(
                             orphan,
                             missing_chunks.iter().map(|header| header.chunk_hash()).collect(),
                         );
+                        self.metrics.blocks_missing_chunks += 1;
 
                         debug!(
                             target: "chain",
@@ -1154,7 +2186,20 @@ impl Chain {
                             msg
                         );
                     }
-                    _ => {}
+                    // These depend on the current head and may pass later.
+                    ErrorKind::InvalidBlockFutureTime(_) | ErrorKind::InvalidBlockHeight(_) => {}
+                    kind => {
+                        if e.is_bad_data() {
+                            if invalidates_block_hash(&kind) && block.check_validity().is_ok() {
+                                self.invalid_blocks.cache_set(*block.hash(), ());
+                            }
+                            *self
+                                .metrics
+                                .invalid_blocks
+                                .entry(mem::discriminant(&kind))
+                                .or_default() += 1;
+                        }
+                    }
                 }
                 if let Err(e) = self.save_block_height_processed(block_height) {
                     warn!(target: "chain", "Failed to save processed height {}: {}", block_height, e);
@@ -1242,6 +2287,76 @@ impl Chain {
         }
     }
 
+    /// Drops blocks that have been waiting for chunks for longer than the orphan pool's
+    /// `max_age_secs`, calling `on_expired` with each block's hash and the chunk headers it was
+    /// still missing. Returns the number of blocks dropped.
+    pub fn expire_blocks_with_missing_chunks<F>(&mut self, mut on_expired: F) -> usize
+    where
+        F: FnMut(CryptoHash, Vec<ShardChunkHeader>),
+    {
+        let max_age = TimeDuration::from_secs(self.orphans.config.max_age_secs);
+        let expired = self
+            .blocks_with_missing_chunks
+            .remove_blocks(|orphan| orphan.added.elapsed() >= max_age);
+        let num_expired = expired.len();
+        for (orphan, missing_chunk_hashes) in expired {
+            let missing_chunks = orphan
+                .block
+                .chunks()
+                .iter()
+                .filter(|chunk| missing_chunk_hashes.contains(&chunk.chunk_hash()))
+                .cloned()
+                .collect::<Vec<_>>();
+            debug!(target: "chain", "Block {} timed out waiting for {} chunks", orphan.block.hash(), missing_chunks.len());
+            on_expired(*orphan.block.hash(), missing_chunks);
+        }
+        num_expired
+    }
+
+    /// Returns blocks waiting for chunks whose missing chunks are due to be requested again,
+    /// with the chunk headers still missing. Chunks are requested when a block starts waiting,
+    /// after which the delay between requests doubles from `CHUNK_REREQUEST_BASE_DELAY` up to
+    /// `CHUNK_REREQUEST_MAX_DELAY`. Returned blocks count as requested at `now`.
+    pub fn chunks_to_rerequest(
+        &mut self,
+        now: Instant,
+    ) -> Vec<(CryptoHash, Vec<ShardChunkHeader>)> {
+        let pool = &self.blocks_with_missing_chunks;
+        self.chunk_rerequests.retain(|block_hash, _| pool.contains(block_hash));
+        let mut due = vec![];
+        for (orphan, missing_chunk_hashes) in pool.waiting_blocks() {
+            let missing_chunk_hashes = match missing_chunk_hashes {
+                Some(missing_chunk_hashes) => missing_chunk_hashes,
+                None => continue,
+            };
+            let (attempts, last_request) = self
+                .chunk_rerequests
+                .entry(*orphan.block.hash())
+                .or_insert_with(|| (0, orphan.added));
+            let delay = std::cmp::min(
+                CHUNK_REREQUEST_BASE_DELAY * 2u32.pow(std::cmp::min(*attempts, 16)),
+                CHUNK_REREQUEST_MAX_DELAY,
+            );
+            if now.saturating_duration_since(*last_request) < delay {
+                continue;
+            }
+            *attempts += 1;
+            *last_request = now;
+            let missing_chunks = orphan
+                .block
+                .chunks()
+                .iter()
+                .filter(|chunk| missing_chunk_hashes.contains(&chunk.chunk_hash()))
+                .cloned()
+                .collect::<Vec<_>>();
+            due.push((orphan.block.header().height(), *orphan.block.hash(), missing_chunks));
+        }
+        due.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        due.into_iter()
+            .map(|(_, block_hash, missing_chunks)| (block_hash, missing_chunks))
+            .collect()
+    }
+
     /// Check for orphans, once a block is successfully added.
     pub fn check_orphans<F, F2, F3>(
         &mut self,
@@ -1251,6 +2366,24 @@ impl Chain {
         block_misses_chunks: F2,
         on_challenge: F3,
     ) -> Option<Tip>
+    where
+        F: Copy + FnMut(AcceptedBlock) -> (),
+        F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
+        F3: Copy + FnMut(ChallengeBody) -> (),
+    {
+        self.unlock_orphans(me, prev_hash, block_accepted, block_misses_chunks, on_challenge).0
+    }
+
+    /// Processes orphans unlocked by `prev_hash`, and the orphans unlocked by those in turn.
+    /// Returns the last new head and the number of orphans accepted.
+    fn unlock_orphans<F, F2, F3>(
+        &mut self,
+        me: &Option<AccountId>,
+        prev_hash: CryptoHash,
+        block_accepted: F,
+        block_misses_chunks: F2,
+        on_challenge: F3,
+    ) -> (Option<Tip>, usize)
     where
         F: Copy + FnMut(AcceptedBlock) -> (),
         F2: Copy + FnMut(Vec<ShardChunkHeader>) -> (),
@@ -1261,10 +2394,11 @@ impl Chain {
 
         let mut maybe_new_head = None;
 
-        // Check if there are orphans we can process.
+        // Check if there are orphans we can process. Orphans are unlocked by the hash of their
+        // previous block, so forks sharing a height are handled independently.
         debug!(target: "chain", "Check orphans: from {}, # orphans {}", prev_hash, self.orphans.len());
         while queue_idx < queue.len() {
-            if let Some(orphans) = self.orphans.remove_by_prev_hash(queue[queue_idx]) {
+            if let Some(orphans) = self.orphans.remove_by_prev_hash(&queue[queue_idx]) {
                 debug!(target: "chain", "Check orphans: found {} orphans", orphans.len());
                 for orphan in orphans.into_iter() {
                     let block_hash = *orphan.block.hash();
@@ -1279,7 +2413,7 @@ impl Chain {
                     );
                     near_metrics::stop_timer(timer);
                     match res {
-                        Ok(maybe_tip) => {
+                        Ok((maybe_tip, _)) => {
                             near_metrics::inc_counter(&metrics::BLOCK_PROCESSED_SUCCESSFULLY_TOTAL);
                             maybe_new_head = maybe_tip;
                             queue.push(block_hash);
@@ -1302,7 +2436,7 @@ impl Chain {
             );
         }
 
-        maybe_new_head
+        (maybe_new_head, queue.len() - 1)
     }
 
     pub fn get_outgoing_receipts_for_shard(
@@ -1761,6 +2895,26 @@ impl Chain {
         Ok(())
     }
 
+    /// Returns ids of state parts of `shard_id` at `sync_hash` that haven't been received yet,
+    /// so state sync can request only those after an interruption.
+    /// Parts that can't be read from the store are reported as missing.
+    pub fn missing_state_parts(
+        &self,
+        shard_id: ShardId,
+        sync_hash: &CryptoHash,
+        num_parts: u64,
+    ) -> Vec<u64> {
+        let store = self.store.owned_store();
+        (0..num_parts)
+            .filter(|part_id| {
+                StatePartKey(*sync_hash, shard_id, *part_id)
+                    .try_to_vec()
+                    .and_then(|key| store.exists(ColStateParts, &key))
+                    .map_or(true, |exists| !exists)
+            })
+            .collect()
+    }
+
     pub fn set_state_finalize(
         &mut self,
         shard_id: ShardId,
@@ -2078,7 +3232,10 @@ impl Chain {
     }
 
     fn chain_update(&mut self) -> ChainUpdate {
-        ChainUpdate::new(
+        let measure_apply_times = self.apply_time_warn_threshold.is_some();
+        let trust_headers = self.trust_headers;
+        let clock = self.clock.clone();
+        let mut chain_update = ChainUpdate::new(
             &mut self.store,
             self.runtime_adapter.clone(),
             &self.orphans,
@@ -2089,7 +3246,35 @@ impl Chain {
             &self.genesis,
             self.transaction_validity_period,
             self.pending_states_to_patch.take(),
-        )
+            self.max_reorg_depth,
+            self.finality_depth,
+            self.acceptable_time_difference,
+            self.tx_observer.as_mut(),
+        );
+        if measure_apply_times {
+            chain_update.apply_times = Some(vec![]);
+        }
+        chain_update.trust_headers = trust_headers;
+        chain_update.clock = clock;
+        chain_update
+    }
+
+    /// Adds chunk application times of processed blocks to the metrics and warns about shards
+    /// that took longer than `apply_time_warn_threshold`.
+    fn record_apply_times(
+        &mut self,
+        apply_times: Option<Vec<(CryptoHash, ShardId, TimeDuration)>>,
+    ) {
+        let threshold = match self.apply_time_warn_threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        for (block_hash, shard_id, apply_time) in apply_times.unwrap_or_default() {
+            if apply_time > threshold {
+                warn!(target: "chain", "Applying chunk of shard {} in block {} took {:?}", shard_id, block_hash, apply_time);
+            }
+            *self.metrics.apply_time_per_shard.entry(shard_id).or_default() += apply_time;
+        }
     }
 
     /// Get node at given position (index, level). If the node does not exist, return `None`.
@@ -2245,6 +3430,88 @@ impl Chain {
         }
         Ok(path)
     }
+
+    /// Get proof that transaction `tx_hash` is included in one of the new chunks of block `block_hash`.
+    pub fn get_tx_inclusion_proof(
+        &mut self,
+        block_hash: &CryptoHash,
+        tx_hash: &CryptoHash,
+    ) -> Result<TxInclusionProof, Error> {
+        let block = self.get_block(block_hash)?.clone();
+        let (_, chunk_proofs) = merklize(
+            &block.chunks().iter().map(|chunk| chunk.tx_root()).collect::<Vec<CryptoHash>>(),
+        );
+        for (chunk_header, chunk_proof) in block.chunks().iter().zip(chunk_proofs.into_iter()) {
+            if chunk_header.height_included() != block.header().height() {
+                continue;
+            }
+            let chunk = self.get_chunk(&chunk_header.chunk_hash())?;
+            if let Some(index) =
+                chunk.transactions().iter().position(|tx| &tx.get_hash() == tx_hash)
+            {
+                let (_, tx_proofs) = merklize(chunk.transactions());
+                return Ok(TxInclusionProof {
+                    block_hash: *block_hash,
+                    shard_id: chunk_header.shard_id(),
+                    chunk_hash: chunk_header.chunk_hash(),
+                    tx_root: chunk_header.tx_root(),
+                    tx_proof: tx_proofs[index].clone(),
+                    chunk_proof,
+                });
+            }
+        }
+        Err(ErrorKind::DBNotFoundErr(format!(
+            "Transaction {} is not found in block {}",
+            tx_hash, block_hash
+        ))
+        .into())
+    }
+
+    /// Returns header of the earliest block on the canonical chain with timestamp at or after `timestamp`.
+    pub fn first_block_on_or_after(
+        &mut self,
+        timestamp: DateTime<Utc>,
+    ) -> Result<BlockHeader, Error> {
+        let head = self.head()?;
+        if self.get_block_header(&head.last_block_hash)?.timestamp() < timestamp {
+            return Err(ErrorKind::DBNotFoundErr(format!(
+                "No block on or after timestamp {}",
+                timestamp
+            ))
+            .into());
+        }
+        // Timestamps are increasing along the canonical chain, so binary search over heights.
+        let mut low = self.genesis.header().height();
+        let mut high = head.height;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.first_canonical_header_from_height(mid, head.height)?.timestamp() >= timestamp {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        self.first_canonical_header_from_height(low, head.height)
+    }
+
+    /// Returns header of the first block on the canonical chain with height in `from..=to`.
+    fn first_canonical_header_from_height(
+        &mut self,
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> Result<BlockHeader, Error> {
+        for height in from..=to {
+            match self.get_header_by_height(height) {
+                Ok(header) => return Ok(header.clone()),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+        Err(ErrorKind::DBNotFoundErr(format!("No block between heights {} and {}", from, to))
+            .into())
+    }
 }
 
 /// Various chain getters.
@@ -2273,24 +3540,52 @@ impl Chain {
         self.store.head_header()
     }
 
+    /// Same as `head_header`, but reads the header from the database past the header cache so
+    /// that it only needs a shared borrow of the chain.
+    pub fn head_header_cloned(&self) -> Result<BlockHeader, Error> {
+        let head_hash = self.head()?.last_block_hash;
+        option_to_not_found(
+            self.store.store().get_ser(ColBlockHeader, head_hash.as_ref()),
+            &format!("BLOCK HEADER: {}", head_hash),
+        )
+    }
+
     /// Get final head of the chain.
     #[inline]
     pub fn final_head(&self) -> Result<Tip, Error> {
         self.store.final_head()
     }
 
+    /// Get the head of blocks buried at least `finality_depth` heights under the head. Fails with
+    /// `DBNotFoundErr` until a block is finalized this way.
+    #[inline]
+    pub fn finalized_head(&self) -> Result<Tip, Error> {
+        self.store.finalized_head()
+    }
+
     /// Gets a block by hash.
     #[inline]
     pub fn get_block(&mut self, hash: &CryptoHash) -> Result<&Block, Error> {
         self.store.get_block(hash)
     }
 
+    /// Gets the size of the serialized block in bytes.
+    #[inline]
+    pub fn get_block_size(&mut self, hash: &CryptoHash) -> Result<usize, Error> {
+        self.store.get_block_size(hash)
+    }
+
     /// Gets a chunk from hash.
     #[inline]
     pub fn get_chunk(&mut self, chunk_hash: &ChunkHash) -> Result<&ShardChunk, Error> {
         self.store.get_chunk(chunk_hash)
     }
 
+    /// Gets number of transactions in a chunk.
+    pub fn get_chunk_tx_count(&mut self, chunk_hash: &ChunkHash) -> Result<usize, Error> {
+        Ok(self.get_chunk(chunk_hash)?.transactions().len())
+    }
+
     /// Gets a chunk from header.
     #[inline]
     pub fn get_chunk_clone_from_header(
@@ -2307,36 +3602,387 @@ impl Chain {
         self.store.get_block(&hash)
     }
 
-    /// Gets a block header by hash.
-    #[inline]
-    pub fn get_block_header(&mut self, hash: &CryptoHash) -> Result<&BlockHeader, Error> {
-        self.store.get_block_header(hash)
-    }
-
-    /// Returns block header from the canonical chain for given height if present.
-    #[inline]
-    pub fn get_header_by_height(&mut self, height: BlockHeight) -> Result<&BlockHeader, Error> {
-        self.store.get_header_by_height(height)
+    /// Gets blocks of the canonical chain with heights in `[start, end]`, in increasing order of
+    /// height. Blocks are looked up via `get_block_hash_by_height`, so blocks on forks are never
+    /// returned. Heights without a block are skipped.
+    pub fn get_blocks_by_height_range(
+        &mut self,
+        start: BlockHeight,
+        end: BlockHeight,
+    ) -> Result<Vec<Block>, Error> {
+        let mut blocks = vec![];
+        for height in start..=end {
+            let block = self
+                .store
+                .get_block_hash_by_height(height)
+                .and_then(|hash| self.store.get_block(&hash).map(Clone::clone));
+            match block {
+                Ok(block) => blocks.push(block),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(blocks)
     }
 
-    /// Returns block header from the current chain defined by `sync_hash` for given height if present.
-    #[inline]
-    pub fn get_header_on_chain_by_height(
+    /// Gets headers of the canonical chain with heights in `[from, to]`, in increasing order of
+    /// height, e.g. to distribute them as a checkpoint. Heights without a block are skipped.
+    pub fn export_headers(
         &mut self,
-        sync_hash: &CryptoHash,
-        height: BlockHeight,
-    ) -> Result<&BlockHeader, Error> {
-        self.store.get_header_on_chain_by_height(sync_hash, height)
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> Result<Vec<BlockHeader>, Error> {
+        let mut headers = vec![];
+        for height in from..=to {
+            match self.store.get_header_by_height(height) {
+                Ok(header) => headers.push(header.clone()),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(headers)
     }
 
-    /// Get previous block header.
-    #[inline]
-    pub fn get_previous_header(&mut self, header: &BlockHeader) -> Result<&BlockHeader, Error> {
-        self.store.get_previous_header(header)
+    /// Validates and stores headers produced by `export_headers`, like headers received during
+    /// header sync. Double sign challenges found on the way are dropped.
+    pub fn import_headers(&mut self, headers: Vec<BlockHeader>) -> Result<(), Error> {
+        self.sync_block_headers(headers, |_| {})
     }
 
-    /// Returns hash of the first available block after genesis.
-    pub fn get_earliest_block_hash(&mut self) -> Result<Option<CryptoHash>, Error> {
+    /// Gets the receipts destined to `shard_id` that were delivered by the canonical blocks with
+    /// heights in `[start, end]`, in increasing order of height. Heights without a block and
+    /// blocks without incoming receipts for the shard are skipped. Incoming receipts are only
+    /// stored for shards this node tracks.
+    pub fn get_incoming_receipts_range(
+        &mut self,
+        shard_id: ShardId,
+        start: BlockHeight,
+        end: BlockHeight,
+    ) -> Result<Vec<(BlockHeight, Vec<Receipt>)>, Error> {
+        let mut result = vec![];
+        for height in start..=end {
+            let receipts = self.store.get_block_hash_by_height(height).and_then(|hash| {
+                self.store.get_incoming_receipts(&hash, shard_id).map(collect_receipts)
+            });
+            match receipts {
+                Ok(receipts) if receipts.is_empty() => {}
+                Ok(receipts) => result.push((height, receipts)),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            }
+        }
+        Ok(result)
+    }
+
+    /// Gets the chunk of `shard_id` included in the canonical block at `height`.
+    /// Fails if the shard has no new chunk at that height.
+    pub fn get_chunk_at(
+        &mut self,
+        height: BlockHeight,
+        shard_id: ShardId,
+    ) -> Result<ShardChunk, Error> {
+        let chunk_header = self
+            .get_block_by_height(height)?
+            .chunks()
+            .get(shard_id as usize)
+            .cloned()
+            .ok_or_else(|| Error::from(ErrorKind::InvalidShardId(shard_id)))?;
+        if chunk_header.height_included() != height {
+            return Err(ErrorKind::ChunkMissing(chunk_header.chunk_hash()).into());
+        }
+        self.get_chunk_clone_from_header(&chunk_header)
+    }
+
+    /// Recomputes the state root of every canonical block with height in `from..=to` from its
+    /// chunks and returns heights where it doesn't match the `prev_state_root` in the stored
+    /// header. Heights without a canonical block are skipped.
+    pub fn verify_block_merkle(
+        &mut self,
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> Result<Vec<BlockHeight>, Error> {
+        let mut mismatched = vec![];
+        for height in from..=to {
+            let block = match self.get_block_by_height(height) {
+                Ok(block) => block,
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            };
+            if &Block::compute_state_root(block.chunks().iter()) != block.header().prev_state_root()
+            {
+                mismatched.push(height);
+            }
+        }
+        Ok(mismatched)
+    }
+
+    /// Gets a block header by hash.
+    #[inline]
+    pub fn get_block_header(&mut self, hash: &CryptoHash) -> Result<&BlockHeader, Error> {
+        self.store.get_block_header(hash)
+    }
+
+    /// Returns the height of the block. Only the header is read, and it's usually cached.
+    pub fn get_block_height(&mut self, hash: &CryptoHash) -> Result<BlockHeight, Error> {
+        Ok(self.get_block_header(hash)?.height())
+    }
+
+    /// Returns the epoch of the block, as recorded in its header.
+    pub fn get_epoch_id(&mut self, hash: &CryptoHash) -> Result<EpochId, Error> {
+        Ok(self.get_block_header(hash)?.epoch_id().clone())
+    }
+
+    /// Returns whether the block is the first block of its epoch. Genesis starts the first epoch.
+    pub fn is_epoch_start_block(&mut self, hash: &CryptoHash) -> Result<bool, Error> {
+        if hash == self.genesis.hash() {
+            return Ok(true);
+        }
+        let header = self.get_block_header(hash)?;
+        let epoch_id = header.epoch_id().clone();
+        let prev_hash = *header.prev_hash();
+        Ok(self.get_block_header(&prev_hash)?.epoch_id() != &epoch_id)
+    }
+
+    /// Returns block producers for the `epoch_length` heights starting at the first block of the
+    /// epoch. The epoch has to be on the canonical chain or be the next epoch of the head, in
+    /// which case it is assumed to start right after the current epoch.
+    pub fn block_producer_schedule(
+        &mut self,
+        epoch_id: &EpochId,
+    ) -> Result<Vec<(BlockHeight, AccountId)>, Error> {
+        let head = self.head()?;
+        let start_height = if epoch_id == &head.next_epoch_id && epoch_id != &head.epoch_id {
+            self.epoch_start_height_on_chain(&head.epoch_id)? + self.epoch_length
+        } else {
+            self.epoch_start_height_on_chain(epoch_id)?
+        };
+        (start_height..start_height + self.epoch_length)
+            .map(|height| Ok((height, self.runtime_adapter.get_block_producer(epoch_id, height)?)))
+            .collect()
+    }
+
+    /// Returns the block producer for the given height on the current chain. Heights after the
+    /// head are assumed to belong to the head's epoch or the next one, each spanning
+    /// `epoch_length` heights. Fails with `EpochOutOfBounds` for heights past the next epoch.
+    pub fn get_block_producer(&mut self, height: BlockHeight) -> Result<AccountId, Error> {
+        let epoch_id = self.epoch_id_for_height(height)?;
+        self.runtime_adapter.get_block_producer(&epoch_id, height)
+    }
+
+    /// Returns the producer of the chunk of `shard_id` at the given height on the current chain.
+    /// Epochs are determined as in `get_block_producer`.
+    pub fn get_chunk_producer(
+        &mut self,
+        height: BlockHeight,
+        shard_id: ShardId,
+    ) -> Result<AccountId, Error> {
+        let epoch_id = self.epoch_id_for_height(height)?;
+        self.runtime_adapter.get_chunk_producer(&epoch_id, height, shard_id)
+    }
+
+    /// Epoch of the given height on the current chain, see `get_block_producer`.
+    fn epoch_id_for_height(&mut self, height: BlockHeight) -> Result<EpochId, Error> {
+        let head = self.head()?;
+        let epoch_start_height = self.epoch_start_height_on_chain(&head.epoch_id)?;
+        let epoch_id = if height < epoch_start_height {
+            match self.get_header_by_height(height) {
+                Ok(header) => header.epoch_id().clone(),
+                Err(_) => return Err(ErrorKind::EpochOutOfBounds(head.epoch_id).into()),
+            }
+        } else if height < epoch_start_height + self.epoch_length {
+            head.epoch_id
+        } else if height < epoch_start_height + 2 * self.epoch_length {
+            head.next_epoch_id
+        } else {
+            return Err(ErrorKind::EpochOutOfBounds(head.next_epoch_id).into());
+        };
+        Ok(epoch_id)
+    }
+
+    /// Finds height of the first block of the epoch on the canonical chain, going back from the
+    /// head one epoch at a time.
+    fn epoch_start_height_on_chain(&mut self, epoch_id: &EpochId) -> Result<BlockHeight, Error> {
+        if !self.runtime_adapter.epoch_exists(epoch_id) {
+            return Err(ErrorKind::EpochOutOfBounds(epoch_id.clone()).into());
+        }
+        let mut block_hash = self.head()?.last_block_hash;
+        loop {
+            let start_height = self.runtime_adapter.get_epoch_start_height(&block_hash)?;
+            if self.get_block_header(&block_hash)?.epoch_id() == epoch_id {
+                return Ok(start_height);
+            }
+            if start_height <= self.genesis.header().height() {
+                return Err(ErrorKind::EpochOutOfBounds(epoch_id.clone()).into());
+            }
+            // The last block of the previous epoch.
+            block_hash = *self.get_header_by_height(start_height)?.prev_hash();
+        }
+    }
+
+    /// Looks for two different known blocks at the given height produced by the same block
+    /// producer, including blocks on forks. Returns their headers if found.
+    pub fn detect_equivocation(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<Option<EquivocationProof>, Error> {
+        find_equivocation(&mut self.store, &*self.runtime_adapter, height, None)
+    }
+
+    /// Keeps the evidence if the accepted block is an equivocation. The block itself stays
+    /// accepted, as blocks signed twice are still valid.
+    fn record_equivocation(&mut self, block_hash: &CryptoHash) {
+        let proof = self.get_block_header(block_hash).map(Clone::clone).and_then(|header| {
+            find_equivocation(
+                &mut self.store,
+                &*self.runtime_adapter,
+                header.height(),
+                Some(&header),
+            )
+        });
+        match proof {
+            Ok(Some(proof)) => {
+                warn!(target: "chain", "Block producer {} produced two blocks at height {}", proof.producer, proof.right_block_header.height());
+                self.equivocation_evidence.push(proof);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(target: "chain", "Failed to check block {} for equivocation: {}", block_hash, e)
+            }
+        }
+    }
+
+    /// Returns equivocations found among accepted blocks since the last call.
+    pub fn take_equivocation_evidence(&mut self) -> Vec<EquivocationProof> {
+        mem::take(&mut self.equivocation_evidence)
+    }
+
+    /// Returns tips of all known blocks at the height of the head, the head itself first and
+    /// competing blocks after it, ordered by hash.
+    pub fn forks_at_head(&mut self) -> Result<Vec<Tip>, Error> {
+        let head = self.head()?;
+        let mut fork_hashes = self
+            .store
+            .get_all_block_hashes_by_height(head.height)?
+            .values()
+            .flatten()
+            .filter(|hash| **hash != head.last_block_hash)
+            .cloned()
+            .collect::<Vec<_>>();
+        fork_hashes.sort();
+        let mut tips = vec![head];
+        for hash in fork_hashes {
+            tips.push(Tip::from_header(self.get_block_header(&hash)?));
+        }
+        Ok(tips)
+    }
+
+    /// Returns block header from the canonical chain for given height if present.
+    #[inline]
+    pub fn get_header_by_height(&mut self, height: BlockHeight) -> Result<&BlockHeader, Error> {
+        self.store.get_header_by_height(height)
+    }
+
+    /// Records the current head, so that a series of reads can be resolved against it even if
+    /// the chain advances in between.
+    pub fn freeze_head(&self) -> Result<HeadGuard, Error> {
+        Ok(HeadGuard { head: self.head()? })
+    }
+
+    /// Gets a block header by height from the chain ending at the frozen head.
+    pub fn get_frozen_header_by_height(
+        &mut self,
+        guard: &HeadGuard,
+        height: BlockHeight,
+    ) -> Result<&BlockHeader, Error> {
+        let head = guard.head();
+        if height > head.height {
+            return Err(ErrorKind::DBNotFoundErr(format!("BLOCK HEIGHT: {}", height)).into());
+        }
+        // While the frozen head is still canonical, the height index can be used directly.
+        let head_is_canonical = match self.store.get_block_hash_by_height(head.height) {
+            Ok(hash) => hash == head.last_block_hash,
+            Err(_) => false,
+        };
+        if head_is_canonical {
+            self.store.get_header_by_height(height)
+        } else {
+            self.store.get_header_on_chain_by_height(&head.last_block_hash, height)
+        }
+    }
+
+    /// Gets a block by height from the chain ending at the frozen head.
+    pub fn get_frozen_block_by_height(
+        &mut self,
+        guard: &HeadGuard,
+        height: BlockHeight,
+    ) -> Result<&Block, Error> {
+        let hash = *self.get_frozen_header_by_height(guard, height)?.hash();
+        self.store.get_block(&hash)
+    }
+
+    /// Returns block header from the current chain defined by `sync_hash` for given height if present.
+    #[inline]
+    pub fn get_header_on_chain_by_height(
+        &mut self,
+        sync_hash: &CryptoHash,
+        height: BlockHeight,
+    ) -> Result<&BlockHeader, Error> {
+        self.store.get_header_on_chain_by_height(sync_hash, height)
+    }
+
+    /// Get previous block header.
+    #[inline]
+    pub fn get_previous_header(&mut self, header: &BlockHeader) -> Result<&BlockHeader, Error> {
+        self.store.get_previous_header(header)
+    }
+
+    /// Iterates over headers from the given block back to genesis, including both.
+    pub fn ancestors(&mut self, start: &CryptoHash) -> AncestorIter {
+        AncestorIter { chain: self, next: Some(*start) }
+    }
+
+    /// Sums the gas used of the new chunks included in the blocks after `ancestor` up to and
+    /// including `descendant`. Chunk headers report the gas used by the previous chunk of their
+    /// shard, so this is the gas of the chunks applied in between. Fails if `ancestor` is not an
+    /// ancestor of `descendant`; the walk back stops as soon as it passes `ancestor`'s height.
+    pub fn gas_used_between(
+        &mut self,
+        ancestor: &CryptoHash,
+        descendant: &CryptoHash,
+    ) -> Result<Gas, Error> {
+        let ancestor_height = self.get_block_header(ancestor)?.height();
+        let mut gas_used: Gas = 0;
+        let mut hash = *descendant;
+        while hash != *ancestor {
+            let block = self.get_block(&hash)?;
+            let height = block.header().height();
+            if height <= ancestor_height {
+                return Err(ErrorKind::Other(format!(
+                    "{} is not an ancestor of {}",
+                    ancestor, descendant
+                ))
+                .into());
+            }
+            gas_used = block
+                .chunks()
+                .iter()
+                .filter(|chunk| chunk.height_included() == height)
+                .fold(gas_used, |total, chunk| total.saturating_add(chunk.gas_used()));
+            hash = *block.header().prev_hash();
+        }
+        Ok(gas_used)
+    }
+
+    /// Returns hash of the first available block after genesis.
+    pub fn get_earliest_block_hash(&mut self) -> Result<Option<CryptoHash>, Error> {
         self.store.get_earliest_block_hash()
     }
 
@@ -2362,6 +4008,33 @@ impl Chain {
         self.store.get_chunk_extra(block_hash, shard_id)
     }
 
+    /// Whether the state of the shard as of the given block is available, either because the
+    /// chunk was applied or because the state was downloaded and finalized by state sync.
+    pub fn has_shard_state(&mut self, shard_id: ShardId, block_hash: &CryptoHash) -> bool {
+        self.store.get_chunk_extra(block_hash, shard_id).is_ok()
+    }
+
+    /// Returns heights of canonical blocks, from the tail to the head, whose bodies are stored but
+    /// whose state of `shard_id` is not available (see `has_shard_state`).
+    pub fn heights_missing_state(&mut self, shard_id: ShardId) -> Result<Vec<BlockHeight>, Error> {
+        let tail = std::cmp::max(self.store.tail()?, self.genesis.header().height());
+        let head = self.head()?.height;
+        let mut missing = vec![];
+        for height in tail..=head {
+            let hash = match self.store.get_block_hash_by_height(height) {
+                Ok(hash) => hash,
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            };
+            if self.store.block_exists(&hash)? && !self.has_shard_state(shard_id, &hash) {
+                missing.push(height);
+            }
+        }
+        Ok(missing)
+    }
+
     /// Get destination shard id for a given receipt id.
     #[inline]
     pub fn get_shard_id_for_receipt_id(
@@ -2371,6 +4044,26 @@ impl Chain {
         self.store.get_shard_id_for_receipt_id(receipt_id)
     }
 
+    /// Returns the hash of the block following the given one on the canonical chain, skipping
+    /// heights without a block. Fails with `DBNotFoundErr` if the block is not on the canonical
+    /// chain or is its head.
+    pub fn get_next_block_hash(&mut self, hash: &CryptoHash) -> Result<CryptoHash, Error> {
+        let not_found =
+            || -> Error { ErrorKind::DBNotFoundErr(format!("NEXT BLOCK HASH: {}", hash)).into() };
+        let height = self.get_block_header(hash)?.height();
+        if &self.store.get_block_hash_by_height(height)? != hash {
+            return Err(not_found());
+        }
+        let next_hash = *self.store.get_next_block_hash(hash)?;
+        let next_header = self.get_block_header(&next_hash)?;
+        let (next_prev_hash, next_height) = (*next_header.prev_hash(), next_header.height());
+        if &next_prev_hash != hash || self.store.get_block_hash_by_height(next_height)? != next_hash
+        {
+            return Err(not_found());
+        }
+        Ok(next_hash)
+    }
+
     /// Get next block hash for which there is a new chunk for the shard.
     #[inline]
     pub fn get_next_block_hash_with_new_chunk(
@@ -2411,6 +4104,102 @@ impl Chain {
         &self.genesis.header()
     }
 
+    /// Walks the canonical chain over heights in `[from_height, to_height]` and reports every
+    /// block that is missing, lacks its previous header or whose `prev_state_root` doesn't match
+    /// its chunks. Heights skipped on the chain are not issues. Fails only on storage errors.
+    pub fn verify_store_integrity(
+        &mut self,
+        from_height: BlockHeight,
+        to_height: BlockHeight,
+    ) -> Result<Vec<IntegrityIssue>, Error> {
+        let genesis_hash = *self.genesis.hash();
+        let mut issues = vec![];
+        for height in from_height..=to_height {
+            let hash = match self.store.get_block_hash_by_height(height) {
+                Ok(hash) => hash,
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            };
+            let block = match self.store.get_block(&hash) {
+                Ok(block) => block.clone(),
+                Err(e) => match e.kind() {
+                    ErrorKind::DBNotFoundErr(_) => {
+                        issues.push(IntegrityIssue::MissingBlock { height, hash });
+                        continue;
+                    }
+                    _ => return Err(e),
+                },
+            };
+            if hash != genesis_hash {
+                let prev_hash = *block.header().prev_hash();
+                if let Err(e) = self.store.get_block_header(&prev_hash) {
+                    match e.kind() {
+                        ErrorKind::DBNotFoundErr(_) => {
+                            issues.push(IntegrityIssue::MissingPrevHeader {
+                                height,
+                                hash,
+                                prev_hash,
+                            });
+                        }
+                        _ => return Err(e),
+                    }
+                }
+            }
+            if block.header().prev_state_root() != &Block::compute_state_root(block.chunks().iter())
+            {
+                issues.push(IntegrityIssue::StateRootMismatch { height, hash });
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Checks that the genesis in storage matches the genesis built from the config and the
+    /// runtime's genesis state: block hash, number of shards and state roots. Returns an error
+    /// describing the first mismatch.
+    pub fn verify_genesis(&mut self) -> Result<(), Error> {
+        let genesis_hash = *self.genesis.hash();
+        let stored_hash = self.store.get_block_hash_by_height(self.genesis.header().height())?;
+        if stored_hash != genesis_hash {
+            return Err(ErrorKind::Other(format!(
+                "Genesis mismatch between storage and config: {:?} vs {:?}",
+                stored_hash, genesis_hash
+            ))
+            .into());
+        }
+
+        let num_shards = self.runtime_adapter.num_shards();
+        let stored_num_shards = self.store.get_block(&genesis_hash)?.chunks().len() as ShardId;
+        if stored_num_shards != num_shards {
+            return Err(ErrorKind::Other(format!(
+                "Genesis number of shards mismatch between storage and runtime: {} vs {}",
+                stored_num_shards, num_shards
+            ))
+            .into());
+        }
+
+        let (_, state_roots) = self.runtime_adapter.genesis_state();
+        for (shard_id, state_root) in state_roots.iter().enumerate() {
+            let shard_id = shard_id as ShardId;
+            let stored_state_root =
+                *self.store.get_chunk_extra(&genesis_hash, shard_id)?.state_root();
+            if &stored_state_root != state_root {
+                return Err(ErrorKind::Other(format!(
+                    "Genesis state root mismatch for shard {} between storage and runtime: {:?} vs {:?}",
+                    shard_id, stored_state_root, state_root
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns counts of block processing outcomes so far.
+    pub fn metrics_snapshot(&self) -> ChainMetrics {
+        self.metrics.clone()
+    }
+
     /// Returns number of orphans currently in the orphan pool.
     #[inline]
     pub fn orphans_len(&self) -> usize {
@@ -2429,18 +4218,74 @@ impl Chain {
         self.orphans.len_evicted()
     }
 
+    /// Returns total serialized size of orphans currently in the orphan pool.
+    #[inline]
+    pub fn orphans_bytes(&self) -> usize {
+        self.orphans.bytes()
+    }
+
+    /// Returns the missing block with the most orphans waiting on it, and their number.
+    /// Requesting this block first unlocks the largest part of the orphan pool.
+    pub fn most_awaited_parent(&self) -> Option<(CryptoHash, usize)> {
+        let result = self.orphans.most_awaited_parent();
+        if let Some((prev_hash, count)) = result {
+            if count > AWAITED_PARENT_WARN_THRESHOLD {
+                warn!(target: "chain", "{} orphans are waiting on missing block {}", count, prev_hash);
+            }
+        }
+        result
+    }
+
     /// Check if hash is for a known orphan.
     #[inline]
     pub fn is_orphan(&self, hash: &CryptoHash) -> bool {
         self.orphans.contains(hash)
     }
 
+    /// Returns hashes of orphans that were added to the orphan pool within `[start, end]`.
+    pub fn orphans_added_between(&self, start: Instant, end: Instant) -> Vec<CryptoHash> {
+        self.orphans.orphans_added_between(start, end)
+    }
+
+    /// Returns number of orphans in the orphan pool by provenance: (NONE, SYNC, PRODUCED).
+    pub fn orphans_by_provenance(&self) -> (usize, usize, usize) {
+        self.orphans.orphans_by_provenance()
+    }
+
+    /// Iterates over orphans currently held in the orphan pool as (hash, height, provenance).
+    pub fn iter_orphans(&self) -> impl Iterator<Item = (&CryptoHash, BlockHeight, &Provenance)> {
+        self.orphans.iter()
+    }
+
+    /// Replaces the orphan pool limits. Takes effect on the next orphan added.
+    pub fn set_orphan_pool_config(&mut self, config: OrphanPoolConfig) {
+        self.orphans.config = config;
+    }
+
+    /// Drops stale entries from the orphan pool indices.
+    pub fn compact_orphan_pool(&mut self) {
+        self.orphans.compact();
+    }
+
     /// Check if hash is for a known chunk orphan.
     #[inline]
     pub fn is_chunk_orphan(&self, hash: &CryptoHash) -> bool {
         self.blocks_with_missing_chunks.contains(hash)
     }
 
+    /// Shards this node tracks as of the current head.
+    pub fn tracked_shards(&self, me: &Option<AccountId>) -> Vec<ShardId> {
+        let parent_hash = match self.head() {
+            Ok(head) => head.prev_block_hash,
+            Err(_) => return vec![],
+        };
+        (0..self.runtime_adapter.num_shards())
+            .filter(|&shard_id| {
+                self.runtime_adapter.cares_about_shard(me.as_ref(), &parent_hash, shard_id, true)
+            })
+            .collect()
+    }
+
     /// Check if can sync with sync_hash
     pub fn check_sync_hash_validity(&mut self, sync_hash: &CryptoHash) -> Result<bool, Error> {
         let head = self.head()?;
@@ -2476,6 +4321,11 @@ impl Chain {
             })
             .ok_or_else(|| ErrorKind::DBNotFoundErr(format!("EXECUTION OUTCOME: {}", id)).into())
     }
+
+    /// Returns the hash of the block on the current chain that included the transaction.
+    pub fn get_transaction_block(&mut self, tx_hash: &CryptoHash) -> Result<CryptoHash, Error> {
+        Ok(self.get_execution_outcome(tx_hash)?.block_hash)
+    }
 }
 
 /// Sandbox node specific operations
@@ -2496,6 +4346,66 @@ impl Chain {
     }
 }
 
+/// Block processed by `Chain::stage_block` whose store changes are not committed yet.
+pub struct StagedBlock<'a> {
+    chain_store_update: ChainStoreUpdate<'a>,
+    /// New head, if the block became the head.
+    pub head: Option<Tip>,
+    pub status: BlockStatus,
+    /// The block starts a new epoch and state for the next epoch has to be fetched.
+    pub needs_to_start_fetching_state: bool,
+}
+
+impl<'a> StagedBlock<'a> {
+    /// Adds external changes to be committed atomically with the block.
+    pub fn merge(&mut self, store_update: StoreUpdate) {
+        self.chain_store_update.merge(store_update);
+    }
+
+    /// Commits the block together with all merged changes.
+    pub fn commit(self) -> Result<(), Error> {
+        self.chain_store_update.commit()
+    }
+}
+
+/// Head of the chain recorded by `Chain::freeze_head`. Holding it doesn't block the chain from
+/// advancing, and dropping it has no effect.
+#[derive(Debug, Clone)]
+pub struct HeadGuard {
+    head: Tip,
+}
+
+impl HeadGuard {
+    pub fn head(&self) -> &Tip {
+        &self.head
+    }
+}
+
+/// Iterator over block headers walking back toward genesis, created by `Chain::ancestors`.
+/// If a header is missing, the error is yielded as the last item.
+pub struct AncestorIter<'a> {
+    chain: &'a mut Chain,
+    next: Option<CryptoHash>,
+}
+
+impl<'a> Iterator for AncestorIter<'a> {
+    type Item = Result<BlockHeader, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let hash = self.next.take()?;
+        let is_genesis = &hash == self.chain.genesis.hash();
+        match self.chain.get_block_header(&hash) {
+            Ok(header) => {
+                if !is_genesis {
+                    self.next = Some(*header.prev_hash());
+                }
+                Some(Ok(header.clone()))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
 /// Chain update helper, contains information that is needed to process block
 /// and decide to accept it or reject it.
 /// If rejected nothing will be updated in underlying storage.
@@ -2512,6 +4422,20 @@ pub struct ChainUpdate<'a> {
     #[allow(unused)]
     transaction_validity_period: BlockHeightDelta,
     states_to_patch: Option<Vec<StateRecord>>,
+    max_reorg_depth: Option<BlockHeightDelta>,
+    finality_depth: Option<BlockHeightDelta>,
+    acceptable_time_difference: Duration,
+    tx_observer: Option<&'a mut TxObserver>,
+    /// Time spent in `apply_transactions` per block and shard, if measured.
+    apply_times: Option<Vec<(CryptoHash, ShardId, TimeDuration)>>,
+    /// The header of the processed block was already validated by `process_block_header`.
+    header_validated: bool,
+    /// See `Chain::pause_processing`.
+    processing_paused: bool,
+    /// See `Chain::adv_trust_headers`.
+    trust_headers: bool,
+    /// See `Chain::set_clock`.
+    clock: Arc<dyn Clock>,
 }
 
 impl<'a> ChainUpdate<'a> {
@@ -2526,6 +4450,10 @@ impl<'a> ChainUpdate<'a> {
         genesis: &'a Block,
         transaction_validity_period: BlockHeightDelta,
         states_to_patch: Option<Vec<StateRecord>>,
+        max_reorg_depth: Option<BlockHeightDelta>,
+        finality_depth: Option<BlockHeightDelta>,
+        acceptable_time_difference: Duration,
+        tx_observer: Option<&'a mut TxObserver>,
     ) -> Self {
         let chain_store_update: ChainStoreUpdate<'_> = store.store_update();
         ChainUpdate {
@@ -2539,6 +4467,15 @@ impl<'a> ChainUpdate<'a> {
             genesis,
             transaction_validity_period,
             states_to_patch,
+            max_reorg_depth,
+            finality_depth,
+            acceptable_time_difference,
+            tx_observer,
+            apply_times: None,
+            header_validated: false,
+            processing_paused: false,
+            trust_headers: false,
+            clock: Arc::new(RealClock),
         }
     }
 
@@ -2761,6 +4698,9 @@ impl<'a> ChainUpdate<'a> {
         })
     }
 
+    /// Applies chunks of the block in ascending shard id order. Chunks of one block only consume
+    /// receipts produced by earlier blocks, so the order doesn't affect the result, but keeping
+    /// it fixed makes the order of store writes reproducible.
     fn apply_chunks(
         &mut self,
         me: &Option<AccountId>,
@@ -2778,219 +4718,369 @@ impl<'a> ChainUpdate<'a> {
         let protocol_version =
             self.runtime_adapter.get_epoch_protocol_version(block.header().epoch_id())?;
 
-        for (shard_id, (chunk_header, prev_chunk_header)) in
-            (block.chunks().iter().zip(prev_block.chunks().iter())).enumerate()
-        {
-            let shard_id = shard_id as ShardId;
-            let care_about_shard = match mode {
-                ApplyChunksMode::ThisEpoch => self.runtime_adapter.cares_about_shard(
-                    me.as_ref(),
+        let chunks = block.chunks();
+        let prev_chunks = prev_block.chunks();
+        for shard_id in self.shards_to_apply(me, block.header().prev_hash(), mode) {
+            let chunk_header = &chunks[shard_id as usize];
+            let prev_chunk_header = &prev_chunks[shard_id as usize];
+            if chunk_header.height_included() == block.header().height() {
+                // Validate state root.
+                let prev_chunk_extra = self
+                    .chain_store_update
+                    .get_chunk_extra(&block.header().prev_hash(), shard_id)?
+                    .clone();
+
+                // Validate that all next chunk information matches previous chunk extra.
+                validate_chunk_with_chunk_extra(
+                    // It's safe here to use ChainStore instead of ChainStoreUpdate
+                    // because we're asking prev_chunk_header for already committed block
+                    self.chain_store_update.get_chain_store(),
+                    &*self.runtime_adapter,
                     &block.header().prev_hash(),
+                    &prev_chunk_extra,
+                    prev_chunk_header,
+                    chunk_header,
+                )
+                .map_err(|e| {
+                    debug!(target: "chain", "Failed to validate chunk extra: {:?}", e);
+                    byzantine_assert!(false);
+                    match self.create_chunk_state_challenge(&prev_block, &block, &chunk_header) {
+                        Ok(chunk_state) => {
+                            Error::from(ErrorKind::InvalidChunkState(Box::new(chunk_state)))
+                        }
+                        Err(err) => err,
+                    }
+                })?;
+                let receipt_proof_response: Vec<ReceiptProofResponse> =
+                    self.chain_store_update.get_incoming_receipts_for_shard(
+                        shard_id,
+                        *block.hash(),
+                        prev_chunk_header.height_included(),
+                    )?;
+                let receipts = collect_receipts_from_response(&receipt_proof_response);
+                let chunk =
+                    self.chain_store_update.get_chunk_clone_from_header(&chunk_header.clone())?;
+
+                let transactions = chunk.transactions();
+                if !validate_transactions_order(transactions) {
+                    let merkle_paths = Block::compute_chunk_headers_root(block.chunks().iter()).1;
+                    let chunk_proof = ChunkProofs {
+                        block_header: block.header().try_to_vec().expect("Failed to serialize"),
+                        merkle_proof: merkle_paths[shard_id as usize].clone(),
+                        chunk: MaybeEncodedShardChunk::Decoded(chunk),
+                    };
+                    return Err(Error::from(ErrorKind::InvalidChunkProofs(Box::new(chunk_proof))));
+                }
+
+                if checked_feature!("stable", AccessKeyNonceRange, protocol_version) {
+                    let transaction_validity_period = self.transaction_validity_period;
+                    for transaction in transactions {
+                        self.chain_store_update
+                            .get_chain_store()
+                            .check_transaction_validity_period(
+                                prev_block.header(),
+                                &transaction.transaction.block_hash,
+                                transaction_validity_period,
+                            )
+                            .map_err(|_| Error::from(ErrorKind::InvalidTransactions))?;
+                    }
+                };
+
+                if let Some(tx_observer) = self.tx_observer.as_mut() {
+                    for transaction in transactions {
+                        tx_observer(&transaction.get_hash(), transaction);
+                    }
+                }
+
+                let chunk_inner = chunk.cloned_header().take_inner();
+                let gas_limit = chunk_inner.gas_limit();
+
+                // This variable is responsible for checking to which block we can apply receipts previously lost in apply_chunks
+                // (see https://github.com/near/nearcore/pull/4248/)
+                // We take the first block with existing chunk in the first epoch in which protocol feature
+                // RestoreReceiptsAfterFix was enabled, and put the restored receipts there.
+                let is_first_block_with_chunk_of_version =
+                    check_if_block_is_first_with_chunk_of_version(
+                        &mut self.chain_store_update,
+                        self.runtime_adapter.as_ref(),
+                        &prev_block.hash(),
+                        shard_id,
+                    )?;
+
+                // Apply transactions and receipts.
+                let apply_start = self.apply_times.as_ref().map(|_| Instant::now());
+                let apply_result = self
+                    .runtime_adapter
+                    .apply_transactions(
+                        shard_id,
+                        chunk_inner.prev_state_root(),
+                        chunk_header.height_included(),
+                        block.header().raw_timestamp(),
+                        &chunk_header.prev_block_hash(),
+                        &block.hash(),
+                        &receipts,
+                        chunk.transactions(),
+                        chunk_inner.validator_proposals(),
+                        prev_block.header().gas_price(),
+                        gas_limit,
+                        &block.header().challenges_result(),
+                        *block.header().random_value(),
+                        true,
+                        is_first_block_with_chunk_of_version,
+                        #[cfg(feature = "sandbox")]
+                        self.states_to_patch.take(),
+                        #[cfg(not(feature = "sandbox"))]
+                        None,
+                    )
+                    .map_err(Self::runtime_apply_failed)?;
+                self.record_apply_time(block.hash(), shard_id, apply_start);
+
+                let (outcome_root, outcome_paths) =
+                    ApplyTransactionResult::compute_outcomes_proof(&apply_result.outcomes);
+
+                self.chain_store_update.save_trie_changes(apply_result.trie_changes);
+                // Save state root after applying transactions.
+                self.chain_store_update.save_chunk_extra(
+                    &block.hash(),
                     shard_id,
-                    true,
-                ),
+                    ChunkExtra::new(
+                        &apply_result.new_root,
+                        outcome_root,
+                        apply_result.validator_proposals,
+                        apply_result.total_gas_burnt,
+                        gas_limit,
+                        apply_result.total_balance_burnt,
+                    ),
+                );
+                self.chain_store_update.save_outgoing_receipt(
+                    &block.hash(),
+                    shard_id,
+                    apply_result.receipt_result,
+                );
+                // Save receipt and transaction results.
+                self.chain_store_update.save_outcomes_with_proofs(
+                    &block.hash(),
+                    shard_id,
+                    apply_result.outcomes,
+                    outcome_paths,
+                );
+            } else {
+                let mut new_extra =
+                    self.chain_store_update.get_chunk_extra(&prev_block.hash(), shard_id)?.clone();
+
+                let apply_start = self.apply_times.as_ref().map(|_| Instant::now());
+                let apply_result = self
+                    .runtime_adapter
+                    .apply_transactions(
+                        shard_id,
+                        new_extra.state_root(),
+                        block.header().height(),
+                        block.header().raw_timestamp(),
+                        &prev_block.hash(),
+                        &block.hash(),
+                        &[],
+                        &[],
+                        new_extra.validator_proposals(),
+                        block.header().gas_price(),
+                        new_extra.gas_limit(),
+                        &block.header().challenges_result(),
+                        *block.header().random_value(),
+                        false,
+                        false,
+                        self.states_to_patch.take(),
+                    )
+                    .map_err(Self::runtime_apply_failed)?;
+                self.record_apply_time(block.hash(), shard_id, apply_start);
+
+                self.chain_store_update.save_trie_changes(apply_result.trie_changes);
+                *new_extra.state_root_mut() = apply_result.new_root;
+
+                self.chain_store_update.save_chunk_extra(&block.hash(), shard_id, new_extra);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_apply_time(
+        &mut self,
+        block_hash: &CryptoHash,
+        shard_id: ShardId,
+        apply_start: Option<Instant>,
+    ) {
+        if let (Some(apply_start), Some(apply_times)) = (apply_start, self.apply_times.as_mut()) {
+            apply_times.push((*block_hash, shard_id, apply_start.elapsed()));
+        }
+    }
+
+    /// Shards whose chunks `apply_chunks` applies in the given mode for a block on top of
+    /// `prev_hash`, in ascending order.
+    fn shards_to_apply(
+        &self,
+        me: &Option<AccountId>,
+        prev_hash: &CryptoHash,
+        mode: ApplyChunksMode,
+    ) -> Vec<ShardId> {
+        (0..self.runtime_adapter.num_shards())
+            .filter(|&shard_id| match mode {
+                ApplyChunksMode::ThisEpoch => {
+                    self.runtime_adapter.cares_about_shard(me.as_ref(), prev_hash, shard_id, true)
+                }
                 ApplyChunksMode::NextEpoch => {
                     self.runtime_adapter.will_care_about_shard(
                         me.as_ref(),
-                        &block.header().prev_hash(),
+                        prev_hash,
                         shard_id,
                         true,
                     ) && !self.runtime_adapter.cares_about_shard(
                         me.as_ref(),
-                        &block.header().prev_hash(),
+                        prev_hash,
                         shard_id,
                         true,
                     )
                 }
-            };
-            if care_about_shard {
-                if chunk_header.height_included() == block.header().height() {
-                    // Validate state root.
-                    let prev_chunk_extra = self
-                        .chain_store_update
-                        .get_chunk_extra(&block.header().prev_hash(), shard_id)?
-                        .clone();
-
-                    // Validate that all next chunk information matches previous chunk extra.
-                    validate_chunk_with_chunk_extra(
-                        // It's safe here to use ChainStore instead of ChainStoreUpdate
-                        // because we're asking prev_chunk_header for already committed block
-                        self.chain_store_update.get_chain_store(),
-                        &*self.runtime_adapter,
-                        &block.header().prev_hash(),
-                        &prev_chunk_extra,
-                        prev_chunk_header,
-                        chunk_header,
-                    )
-                    .map_err(|e| {
-                        debug!(target: "chain", "Failed to validate chunk extra: {:?}", e);
-                        byzantine_assert!(false);
-                        match self.create_chunk_state_challenge(&prev_block, &block, &chunk_header)
-                        {
-                            Ok(chunk_state) => {
-                                Error::from(ErrorKind::InvalidChunkState(Box::new(chunk_state)))
-                            }
-                            Err(err) => err,
-                        }
-                    })?;
-                    let receipt_proof_response: Vec<ReceiptProofResponse> =
-                        self.chain_store_update.get_incoming_receipts_for_shard(
-                            shard_id,
-                            *block.hash(),
-                            prev_chunk_header.height_included(),
-                        )?;
-                    let receipts = collect_receipts_from_response(&receipt_proof_response);
-                    let chunk = self
-                        .chain_store_update
-                        .get_chunk_clone_from_header(&chunk_header.clone())?;
-
-                    let transactions = chunk.transactions();
-                    if !validate_transactions_order(transactions) {
-                        let merkle_paths =
-                            Block::compute_chunk_headers_root(block.chunks().iter()).1;
-                        let chunk_proof = ChunkProofs {
-                            block_header: block.header().try_to_vec().expect("Failed to serialize"),
-                            merkle_proof: merkle_paths[shard_id as usize].clone(),
-                            chunk: MaybeEncodedShardChunk::Decoded(chunk),
-                        };
-                        return Err(Error::from(ErrorKind::InvalidChunkProofs(Box::new(
-                            chunk_proof,
-                        ))));
-                    }
+            })
+            .collect()
+    }
 
-                    if checked_feature!("stable", AccessKeyNonceRange, protocol_version) {
-                        let transaction_validity_period = self.transaction_validity_period;
-                        for transaction in transactions {
-                            self.chain_store_update
-                                .get_chain_store()
-                                .check_transaction_validity_period(
-                                    prev_block.header(),
-                                    &transaction.transaction.block_hash,
-                                    transaction_validity_period,
-                                )
-                                .map_err(|_| Error::from(ErrorKind::InvalidTransactions))?;
-                        }
-                    };
+    /// Checks that the chunk is signed by the chunk producer for its shard and height.
+    fn verify_chunk_producer(&self, chunk_header: &ShardChunkHeader) -> Result<(), Error> {
+        let prev_block_hash = chunk_header.prev_block_hash();
+        let epoch_id = self.runtime_adapter.get_epoch_id_from_prev_block(&prev_block_hash)?;
+        let chunk_producer = self.runtime_adapter.get_chunk_producer(
+            &epoch_id,
+            chunk_header.height_created(),
+            chunk_header.shard_id(),
+        )?;
+        let (validator, is_slashed) = self.runtime_adapter.get_validator_by_account_id(
+            &epoch_id,
+            &prev_block_hash,
+            &chunk_producer,
+        )?;
+        let chunk_hash = chunk_header.chunk_hash();
+        if is_slashed
+            || !chunk_header.signature().verify(chunk_hash.as_ref(), validator.public_key())
+        {
+            return Err(ErrorKind::InvalidChunk.into());
+        }
+        Ok(())
+    }
 
-                    let chunk_inner = chunk.cloned_header().take_inner();
-                    let gas_limit = chunk_inner.gas_limit();
-
-                    // This variable is responsible for checking to which block we can apply receipts previously lost in apply_chunks
-                    // (see https://github.com/near/nearcore/pull/4248/)
-                    // We take the first block with existing chunk in the first epoch in which protocol feature
-                    // RestoreReceiptsAfterFix was enabled, and put the restored receipts there.
-                    let is_first_block_with_chunk_of_version =
-                        check_if_block_is_first_with_chunk_of_version(
-                            &mut self.chain_store_update,
-                            self.runtime_adapter.as_ref(),
-                            &prev_block.hash(),
-                            shard_id,
-                        )?;
-
-                    // Apply transactions and receipts.
-                    let apply_result = self
-                        .runtime_adapter
-                        .apply_transactions(
-                            shard_id,
-                            chunk_inner.prev_state_root(),
-                            chunk_header.height_included(),
-                            block.header().raw_timestamp(),
-                            &chunk_header.prev_block_hash(),
-                            &block.hash(),
-                            &receipts,
-                            chunk.transactions(),
-                            chunk_inner.validator_proposals(),
-                            prev_block.header().gas_price(),
-                            gas_limit,
-                            &block.header().challenges_result(),
-                            *block.header().random_value(),
-                            true,
-                            is_first_block_with_chunk_of_version,
-                            #[cfg(feature = "sandbox")]
-                            self.states_to_patch.take(),
-                            #[cfg(not(feature = "sandbox"))]
-                            None,
-                        )
-                        .map_err(|e| ErrorKind::Other(e.to_string()))?;
+    /// Runs the block processing, including validation and finding a place for the new block in the chain.
+    /// Returns new head if chain head updated, as well as a boolean indicating if we need to start
+    ///    fetching state for the next epoch.
+    fn process_block<F>(
+        &mut self,
+        me: &Option<AccountId>,
+        block: &Block,
+        provenance: &Provenance,
+        on_challenge: F,
+    ) -> Result<(Option<Tip>, bool), Error>
+    where
+        F: FnMut(ChallengeBody) -> (),
+    {
+        let (prev_block, is_caught_up, needs_to_start_fetching_state) =
+            self.check_block(me, block, provenance, on_challenge)?;
+        let prev_hash = *prev_block.hash();
+        let prev_epoch_id = prev_block.header().epoch_id().clone();
 
-                    let (outcome_root, outcome_paths) =
-                        ApplyTransactionResult::compute_outcomes_proof(&apply_result.outcomes);
+        self.save_incoming_receipts_from_block(me, &block)?;
 
-                    self.chain_store_update.save_trie_changes(apply_result.trie_changes);
-                    // Save state root after applying transactions.
-                    self.chain_store_update.save_chunk_extra(
-                        &block.hash(),
-                        shard_id,
-                        ChunkExtra::new(
-                            &apply_result.new_root,
-                            outcome_root,
-                            apply_result.validator_proposals,
-                            apply_result.total_gas_burnt,
-                            gas_limit,
-                            apply_result.total_balance_burnt,
-                        ),
-                    );
-                    self.chain_store_update.save_outgoing_receipt(
-                        &block.hash(),
-                        shard_id,
-                        apply_result.receipt_result,
-                    );
-                    // Save receipt and transaction results.
-                    self.chain_store_update.save_outcomes_with_proofs(
-                        &block.hash(),
-                        shard_id,
-                        apply_result.outcomes,
-                        outcome_paths,
-                    );
-                } else {
-                    let mut new_extra = self
-                        .chain_store_update
-                        .get_chunk_extra(&prev_block.hash(), shard_id)?
-                        .clone();
-
-                    let apply_result = self
-                        .runtime_adapter
-                        .apply_transactions(
-                            shard_id,
-                            new_extra.state_root(),
-                            block.header().height(),
-                            block.header().raw_timestamp(),
-                            &prev_block.hash(),
-                            &block.hash(),
-                            &[],
-                            &[],
-                            new_extra.validator_proposals(),
-                            block.header().gas_price(),
-                            new_extra.gas_limit(),
-                            &block.header().challenges_result(),
-                            *block.header().random_value(),
-                            false,
-                            false,
-                            self.states_to_patch.take(),
-                        )
-                        .map_err(|e| ErrorKind::Other(e.to_string()))?;
+        // Always apply state transition for shards in the current epoch
+        self.apply_chunks(me, block, &prev_block, ApplyChunksMode::ThisEpoch)?;
+
+        // If we have the state for the next epoch already downloaded, apply the state transition for the next epoch as well,
+        //    otherwise put the block into the permanent storage to have the state transition applied later
+        if is_caught_up {
+            self.apply_chunks(me, block, &prev_block, ApplyChunksMode::NextEpoch)?;
+        } else {
+            self.chain_store_update.add_block_to_catchup(prev_hash, *block.hash());
+        }
+
+        // Verify that proposals from chunks match block header proposals.
+        let block_height = block.header().height();
+        for pair in block
+            .chunks()
+            .iter()
+            .filter(|chunk| block_height == chunk.height_included())
+            .flat_map(|chunk| chunk.validator_proposals())
+            .zip_longest(block.header().validator_proposals())
+        {
+            match pair {
+                itertools::EitherOrBoth::Both(cp, hp) => {
+                    if hp != cp {
+                        // Proposals differed!
+                        return Err(ErrorKind::InvalidValidatorProposals.into());
+                    }
+                }
+                _ => {
+                    // Can only occur if there were a different number of proposals in the header
+                    // and chunks
+                    return Err(ErrorKind::InvalidValidatorProposals.into());
+                }
+            }
+        }
+
+        // If block checks out, record validator proposals for given block.
+        let last_final_block = block.header().last_final_block();
+        let last_finalized_height = if last_final_block == &CryptoHash::default() {
+            self.chain_store_update.get_genesis_height()
+        } else {
+            self.chain_store_update.get_block_header(last_final_block)?.height()
+        };
+
+        let epoch_manager_update = self.runtime_adapter.add_validator_proposals(
+            BlockHeaderInfo::new(&block.header(), last_finalized_height),
+        )?;
+        self.chain_store_update.merge(epoch_manager_update);
+
+        // Add validated block to the db, even if it's not the canonical fork.
+        self.chain_store_update.save_block(block.clone());
+        self.chain_store_update.inc_block_refcount(block.header().prev_hash())?;
+        for (shard_id, chunk_headers) in block.chunks().iter().enumerate() {
+            if chunk_headers.height_included() == block.header().height() {
+                self.chain_store_update
+                    .save_block_hash_with_new_chunk(*block.hash(), shard_id as ShardId);
+            }
+        }
 
-                    self.chain_store_update.save_trie_changes(apply_result.trie_changes);
-                    *new_extra.state_root_mut() = apply_result.new_root;
+        // Update the chain head if it's the new tip
+        let res = self.update_head(block.header())?;
 
-                    self.chain_store_update.save_chunk_extra(&block.hash(), shard_id, new_extra);
+        if res.is_some() {
+            // On the epoch switch record the epoch light client block
+            // Note that we only do it if `res.is_some()`, i.e. if the current block is the head.
+            // This is necessary because the computation of the light client block relies on
+            // `ColNextBlockHash`-es populated, and they are only populated for the canonical
+            // chain. We need to be careful to avoid a situation when the first block of the epoch
+            // never becomes a tip of the canonical chain.
+            // Presently the epoch boundary is defined by the height, and the fork choice rule
+            // is also height, with ties broken by hash since `ForkChoiceTieBreak`, so the very
+            // first block to cross the epoch end is guaranteed to be the head of the chain, and
+            // result in the light client block produced. A block at the same height that replaces
+            // it as the head crosses the epoch end too, and produces the light client block anew.
+            if block.header().epoch_id() != &prev_epoch_id {
+                let prev = self.get_previous_header(&block.header())?.clone();
+                if prev.last_final_block() != &CryptoHash::default() {
+                    let light_client_block = self.create_light_client_block(&prev)?;
+                    self.chain_store_update
+                        .save_epoch_light_client_block(&prev_epoch_id.0, light_client_block);
                 }
             }
         }
 
-        Ok(())
+        Ok((res, needs_to_start_fetching_state))
     }
 
-    /// Runs the block processing, including validation and finding a place for the new block in the chain.
-    /// Returns new head if chain head updated, as well as a boolean indicating if we need to start
-    ///    fetching state for the next epoch.
-    fn process_block<F>(
+    /// Runs the checks of the block that don't require applying its chunks. Returns the previous
+    /// block, whether it's caught up, and whether state for the next epoch has to be fetched.
+    fn check_block<F>(
         &mut self,
         me: &Option<AccountId>,
         block: &Block,
         provenance: &Provenance,
         on_challenge: F,
-    ) -> Result<(Option<Tip>, bool), Error>
+    ) -> Result<(Block, bool, bool), Error>
     where
         F: FnMut(ChallengeBody) -> (),
     {
@@ -3000,6 +5090,13 @@ impl<'a> ChainUpdate<'a> {
             return Err(ErrorKind::IncorrectNumberOfChunkHeaders.into());
         }
 
+        // Malformed block may include the same chunk several times.
+        let mut chunk_hashes = HashSet::new();
+        if !block.chunks().iter().all(|chunk_header| chunk_hashes.insert(chunk_header.chunk_hash()))
+        {
+            return Err(ErrorKind::InvalidChunk.into());
+        }
+
         // Check if we have already processed this block previously.
         self.check_known(block.header().hash())?;
 
@@ -3020,8 +5117,11 @@ impl<'a> ChainUpdate<'a> {
             return Err(ErrorKind::InvalidBlockHeight(block_height).into());
         }
 
-        // Block is an orphan if we do not know about the previous full block.
-        if !is_next && !self.chain_store_update.block_exists(&block.header().prev_hash())? {
+        // Block is an orphan if we do not know about the previous full block. While processing
+        // is paused, every block is queued as one.
+        if self.processing_paused
+            || (!is_next && !self.chain_store_update.block_exists(&block.header().prev_hash())?)
+        {
             // Before we add the block to the orphan pool, do some checks:
             // 1. Block header is signed by the block producer for height.
             // 2. Chunk headers in block body match block header.
@@ -3044,7 +5144,6 @@ impl<'a> ChainUpdate<'a> {
         let prev_hash = *prev.hash();
         let prev_prev_hash = *prev.prev_hash();
         let prev_gas_price = prev.gas_price();
-        let prev_epoch_id = prev.epoch_id().clone();
         let prev_random_value = *prev.random_value();
         let prev_height = prev.height();
 
@@ -3108,105 +5207,67 @@ impl<'a> ChainUpdate<'a> {
         let prev_block = self.chain_store_update.get_block(&prev_hash)?.clone();
 
         self.ping_missing_chunks(me, prev_hash, &block)?;
-        self.save_incoming_receipts_from_block(me, &block)?;
 
         // Do basic validation of chunks before applying the transactions
         for (chunk_header, prev_chunk_header) in
             block.chunks().iter().zip(prev_block.chunks().iter())
-        {
-            if chunk_header.height_included() == block.header().height() {
-                if &chunk_header.prev_block_hash() != block.header().prev_hash() {
-                    return Err(ErrorKind::InvalidChunk.into());
-                }
-            } else {
-                if prev_chunk_header != chunk_header {
-                    return Err(ErrorKind::InvalidChunk.into());
-                }
-            }
-        }
-
-        // Always apply state transition for shards in the current epoch
-        self.apply_chunks(me, block, &prev_block, ApplyChunksMode::ThisEpoch)?;
-
-        // If we have the state for the next epoch already downloaded, apply the state transition for the next epoch as well,
-        //    otherwise put the block into the permanent storage to have the state transition applied later
-        if is_caught_up {
-            self.apply_chunks(me, block, &prev_block, ApplyChunksMode::NextEpoch)?;
-        } else {
-            self.chain_store_update.add_block_to_catchup(prev_hash, *block.hash());
-        }
-
-        // Verify that proposals from chunks match block header proposals.
-        let block_height = block.header().height();
-        for pair in block
-            .chunks()
-            .iter()
-            .filter(|chunk| block_height == chunk.height_included())
-            .flat_map(|chunk| chunk.validator_proposals())
-            .zip_longest(block.header().validator_proposals())
-        {
-            match pair {
-                itertools::EitherOrBoth::Both(cp, hp) => {
-                    if hp != cp {
-                        // Proposals differed!
-                        return Err(ErrorKind::InvalidValidatorProposals.into());
-                    }
-                }
-                _ => {
-                    // Can only occur if there were a different number of proposals in the header
-                    // and chunks
-                    return Err(ErrorKind::InvalidValidatorProposals.into());
-                }
-            }
-        }
-
-        // If block checks out, record validator proposals for given block.
-        let last_final_block = block.header().last_final_block();
-        let last_finalized_height = if last_final_block == &CryptoHash::default() {
-            self.chain_store_update.get_genesis_height()
-        } else {
-            self.chain_store_update.get_block_header(last_final_block)?.height()
-        };
-
-        let epoch_manager_update = self.runtime_adapter.add_validator_proposals(
-            BlockHeaderInfo::new(&block.header(), last_finalized_height),
-        )?;
-        self.chain_store_update.merge(epoch_manager_update);
-
-        // Add validated block to the db, even if it's not the canonical fork.
-        self.chain_store_update.save_block(block.clone());
-        self.chain_store_update.inc_block_refcount(block.header().prev_hash())?;
-        for (shard_id, chunk_headers) in block.chunks().iter().enumerate() {
-            if chunk_headers.height_included() == block.header().height() {
-                self.chain_store_update
-                    .save_block_hash_with_new_chunk(*block.hash(), shard_id as ShardId);
+        {
+            if chunk_header.height_included() == block.header().height() {
+                if &chunk_header.prev_block_hash() != block.header().prev_hash() {
+                    return Err(ErrorKind::InvalidChunk.into());
+                }
+                // Chunks that came from the network must be signed by the producer assigned to
+                // them.
+                if *provenance != Provenance::PRODUCED {
+                    self.verify_chunk_producer(chunk_header)?;
+                }
+            } else {
+                if prev_chunk_header != chunk_header {
+                    return Err(ErrorKind::InvalidChunk.into());
+                }
             }
         }
 
-        // Update the chain head if it's the new tip
-        let res = self.update_head(block.header())?;
+        Ok((prev_block, is_caught_up, needs_to_start_fetching_state))
+    }
 
-        if res.is_some() {
-            // On the epoch switch record the epoch light client block
-            // Note that we only do it if `res.is_some()`, i.e. if the current block is the head.
-            // This is necessary because the computation of the light client block relies on
-            // `ColNextBlockHash`-es populated, and they are only populated for the canonical
-            // chain. We need to be careful to avoid a situation when the first block of the epoch
-            // never becomes a tip of the canonical chain.
-            // Presently the epoch boundary is defined by the height, and the fork choice rule
-            // is also just height, so the very first block to cross the epoch end is guaranteed
-            // to be the head of the chain, and result in the light client block produced.
-            if block.header().epoch_id() != &prev_epoch_id {
-                let prev = self.get_previous_header(&block.header())?.clone();
-                if prev.last_final_block() != &CryptoHash::default() {
-                    let light_client_block = self.create_light_client_block(&prev)?;
-                    self.chain_store_update
-                        .save_epoch_light_client_block(&prev_epoch_id.0, light_client_block);
-                }
+    /// Checks whether the block would be accepted by `process_block`, short of applying its
+    /// chunks. Chunk state roots are checked against the previous chunk extra of the shards
+    /// this node tracks. Nothing is saved, as long as the update isn't committed.
+    pub fn validate_block(
+        &mut self,
+        me: &Option<AccountId>,
+        block: &Block,
+        provenance: &Provenance,
+    ) -> Result<(), Error> {
+        let (prev_block, _, _) = self.check_block(me, block, provenance, |_| {})?;
+        for (shard_id, (chunk_header, prev_chunk_header)) in
+            block.chunks().iter().zip(prev_block.chunks().iter()).enumerate()
+        {
+            if chunk_header.height_included() != block.header().height() {
+                continue;
             }
+            let prev_chunk_extra = match self
+                .chain_store_update
+                .get_chunk_extra(prev_block.hash(), shard_id as ShardId)
+            {
+                Ok(prev_chunk_extra) => prev_chunk_extra.clone(),
+                Err(e) => match e.kind() {
+                    // State of the shard isn't tracked.
+                    ErrorKind::DBNotFoundErr(_) => continue,
+                    _ => return Err(e),
+                },
+            };
+            validate_chunk_with_chunk_extra(
+                self.chain_store_update.get_chain_store(),
+                &*self.runtime_adapter,
+                prev_block.hash(),
+                &prev_chunk_extra,
+                prev_chunk_header,
+                chunk_header,
+            )?;
         }
-
-        Ok((res, needs_to_start_fetching_state))
+        Ok(())
     }
 
     pub fn create_light_client_block(
@@ -3248,7 +5309,17 @@ impl<'a> ChainUpdate<'a> {
     where
         F: FnMut(ChallengeBody) -> (),
     {
-        self.validate_header(header, provenance, on_challenge)?;
+        // Block producer must support the protocol version of the block's epoch.
+        let epoch_protocol_version =
+            self.runtime_adapter.get_epoch_protocol_version(header.epoch_id())?;
+        if header.latest_protocol_version() < epoch_protocol_version {
+            return Err(ErrorKind::Unfit("protocol version mismatch".to_string()).into());
+        }
+        if self.header_validated {
+            self.validate_header_impl(header, provenance, true, true, on_challenge)?;
+        } else {
+            self.validate_header(header, provenance, on_challenge)?;
+        }
         self.chain_store_update.save_block_header(header.clone())?;
         self.update_header_head_if_not_challenged(header)?;
         Ok(())
@@ -3258,43 +5329,53 @@ impl<'a> ChainUpdate<'a> {
         &mut self,
         header: &BlockHeader,
         provenance: &Provenance,
+        on_challenge: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(ChallengeBody) -> (),
+    {
+        self.validate_header_impl(header, provenance, false, false, on_challenge)
+    }
+
+    /// Validates header, skipping the signature check if `signature_verified` is set and the
+    /// approval and finality checks if `approvals_verified` is set.
+    fn validate_header_impl<F>(
+        &mut self,
+        header: &BlockHeader,
+        provenance: &Provenance,
+        signature_verified: bool,
+        approvals_verified: bool,
         mut on_challenge: F,
     ) -> Result<(), Error>
     where
         F: FnMut(ChallengeBody) -> (),
     {
         // Refuse blocks from the too distant future.
-        if header.timestamp() > Utc::now() + Duration::seconds(ACCEPTABLE_TIME_DIFFERENCE) {
+        if !self.trust_headers
+            && header.timestamp() > self.clock.now() + self.acceptable_time_difference
+        {
             return Err(ErrorKind::InvalidBlockFutureTime(header.timestamp()).into());
         }
 
+        // Header has to carry the chunk mask for exactly as many chunks as there are shards.
+        if header.chunk_mask().len() != self.runtime_adapter.num_shards() as usize {
+            return Err(ErrorKind::IncorrectNumberOfChunkHeaders.into());
+        }
+
         // First I/O cost, delay as much as possible.
-        if !self.runtime_adapter.verify_header_signature(header)? {
+        if !signature_verified && !self.runtime_adapter.verify_header_signature(header)? {
             return Err(ErrorKind::InvalidSignature.into());
         }
 
         // Check we don't know a block with given height already.
         // If we do - send out double sign challenge and keep going as double signed blocks are valid blocks.
-        if let Ok(epoch_id_to_blocks) = self
-            .chain_store_update
-            .get_chain_store()
-            .get_all_block_hashes_by_height(header.height())
-            .map(Clone::clone)
-        {
-            // Check if there is already known block of the same height that has the same epoch id
-            if let Some(block_hashes) = epoch_id_to_blocks.get(&header.epoch_id()) {
-                // This should be guaranteed but it doesn't hurt to check again
-                if !block_hashes.contains(header.hash()) {
-                    let other_header = self
-                        .chain_store_update
-                        .get_block_header(block_hashes.iter().next().unwrap())?;
-
-                    on_challenge(ChallengeBody::BlockDoubleSign(BlockDoubleSign {
-                        left_block_header: header.try_to_vec().expect("Failed to serialize"),
-                        right_block_header: other_header.try_to_vec().expect("Failed to serialize"),
-                    }));
-                }
-            }
+        if let Ok(Some(proof)) = find_equivocation(
+            self.chain_store_update.get_chain_store(),
+            &*self.runtime_adapter,
+            header.height(),
+            Some(header),
+        ) {
+            on_challenge(ChallengeBody::BlockDoubleSign(proof.into()));
         }
 
         let prev_header = self.get_previous_header(header)?.clone();
@@ -3337,25 +5418,12 @@ impl<'a> ChainUpdate<'a> {
             return Err(ErrorKind::InvalidChunkMask.into());
         }
 
-        #[cfg(feature = "protocol_feature_block_header_v3")]
-        if let Some(prev_height) = header.prev_height() {
-            if prev_height != prev_header.height() {
-                return Err(ErrorKind::Other("Invalid prev_height".to_string()).into());
-            }
-        }
-
-        // Prevent time warp attacks and some timestamp manipulations by forcing strict
-        // time progression.
-        if header.raw_timestamp() <= prev_header.raw_timestamp() {
-            return Err(ErrorKind::InvalidBlockPastTime(
-                prev_header.timestamp(),
-                header.timestamp(),
-            )
-            .into());
+        if !self.trust_headers {
+            Self::check_header_progression(header, &prev_header)?;
         }
         // If this is not the block we produced (hence trust in it) - validates block
         // producer, confirmation signatures and finality info.
-        if *provenance != Provenance::PRODUCED {
+        if *provenance != Provenance::PRODUCED && !approvals_verified && !self.trust_headers {
             // first verify aggregated signature
             if !self.runtime_adapter.verify_approval(
                 prev_header.hash(),
@@ -3380,25 +5448,7 @@ impl<'a> ChainUpdate<'a> {
                 return Err(ErrorKind::NotEnoughApprovals.into());
             }
 
-            let expected_last_ds_final_block = if prev_header.height() + 1 == header.height() {
-                prev_header.hash()
-            } else {
-                prev_header.last_ds_final_block()
-            };
-
-            let expected_last_final_block = if prev_header.height() + 1 == header.height()
-                && prev_header.last_ds_final_block() == prev_header.prev_hash()
-            {
-                prev_header.prev_hash()
-            } else {
-                prev_header.last_final_block()
-            };
-
-            if header.last_ds_final_block() != expected_last_ds_final_block
-                || header.last_final_block() != expected_last_final_block
-            {
-                return Err(ErrorKind::InvalidFinalityInfo.into());
-            }
+            Self::check_finality_info(header, &prev_header)?;
 
             let mut block_merkle_tree =
                 self.chain_store_update.get_block_merkle_tree(header.prev_hash())?.clone();
@@ -3411,6 +5461,68 @@ impl<'a> ChainUpdate<'a> {
         Ok(())
     }
 
+    /// Wraps an error from `apply_transactions`, telling apart failures of this node's storage,
+    /// which may pass on retry, from chunks that no node can apply.
+    fn runtime_apply_failed(err: Error) -> Error {
+        let failure = match err.kind() {
+            ErrorKind::StorageError(storage_err) => ApplyChunkFailure::StorageError(storage_err),
+            _ if err.is_bad_data() => ApplyChunkFailure::InvalidChunk(err.to_string()),
+            _ => ApplyChunkFailure::Other(err.to_string()),
+        };
+        ErrorKind::RuntimeApplyFailed(failure).into()
+    }
+
+    /// Checks of a header against its previous header that need no access to the store.
+    fn check_header_progression(
+        header: &BlockHeader,
+        prev_header: &BlockHeader,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "protocol_feature_block_header_v3")]
+        if let Some(prev_height) = header.prev_height() {
+            if prev_height != prev_header.height() {
+                return Err(ErrorKind::Other("Invalid prev_height".to_string()).into());
+            }
+        }
+
+        // Prevent time warp attacks and some timestamp manipulations by forcing strict
+        // time progression.
+        if header.raw_timestamp() == prev_header.raw_timestamp() {
+            return Err(ErrorKind::DuplicateBlockTime(header.timestamp()).into());
+        }
+        if header.raw_timestamp() < prev_header.raw_timestamp() {
+            return Err(ErrorKind::InvalidBlockPastTime(
+                prev_header.timestamp(),
+                header.timestamp(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the finality info of a header follows from its previous header.
+    fn check_finality_info(header: &BlockHeader, prev_header: &BlockHeader) -> Result<(), Error> {
+        let expected_last_ds_final_block = if prev_header.height() + 1 == header.height() {
+            prev_header.hash()
+        } else {
+            prev_header.last_ds_final_block()
+        };
+
+        let expected_last_final_block = if prev_header.height() + 1 == header.height()
+            && prev_header.last_ds_final_block() == prev_header.prev_hash()
+        {
+            prev_header.prev_hash()
+        } else {
+            prev_header.last_final_block()
+        };
+
+        if header.last_ds_final_block() != expected_last_ds_final_block
+            || header.last_final_block() != expected_last_final_block
+        {
+            return Err(ErrorKind::InvalidFinalityInfo.into());
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "protocol_feature_block_header_v3")]
     #[allow(dead_code)]
     fn verify_orphan_header_approvals(&mut self, header: &BlockHeader) -> Result<(), Error> {
@@ -3436,13 +5548,29 @@ impl<'a> ChainUpdate<'a> {
         )
     }
 
+    /// Fork choice rule: the higher block wins. Since `ForkChoiceTieBreak`, between blocks at the
+    /// same height the one with the lexicographically smaller hash wins, so that every node
+    /// settles on the same head no matter in which order the blocks arrived.
+    fn is_better_than_tip(&self, header: &BlockHeader, tip: &Tip) -> Result<bool, Error> {
+        if header.height() != tip.height {
+            return Ok(header.height() > tip.height);
+        }
+        let protocol_version =
+            self.runtime_adapter.get_epoch_protocol_version(header.epoch_id())?;
+        Ok(checked_feature!(
+            "protocol_feature_fork_choice_tie_break",
+            ForkChoiceTieBreak,
+            protocol_version
+        ) && header.hash() < &tip.last_block_hash)
+    }
+
     /// Update the header head if this header has most work.
     fn update_header_head_if_not_challenged(
         &mut self,
         header: &BlockHeader,
     ) -> Result<Option<Tip>, Error> {
         let header_head = self.chain_store_update.header_head()?;
-        if header.height() > header_head.height {
+        if self.is_better_than_tip(header, &header_head)? {
             let tip = Tip::from_header(header);
             self.chain_store_update.save_header_head_if_not_challenged(&tip)?;
             debug!(target: "chain", "Header head updated to {} at {}", tip.last_block_hash, tip.height);
@@ -3475,22 +5603,119 @@ impl<'a> ChainUpdate<'a> {
     /// Directly updates the head if we've just appended a new block to it or handle
     /// the situation where the block has higher height to have a fork
     fn update_head(&mut self, header: &BlockHeader) -> Result<Option<Tip>, Error> {
+        let prev_finalized_head =
+            if self.finality_depth.is_some() { self.get_finalized_head()? } else { None };
         // if we made a fork with higher height than the head (which should also be true
         // when extending the head), update it
         self.update_final_head_from_block(header)?;
         let head = self.chain_store_update.head()?;
-        if header.height() > head.height {
+        if self.is_better_than_tip(header, &head)? {
+            if header.prev_hash() != &head.last_block_hash
+                && (self.max_reorg_depth.is_some() || self.finality_depth.is_some())
+            {
+                let common_ancestor =
+                    self.find_common_ancestor(&head.last_block_hash, header.prev_hash())?;
+                let depth = head.height - common_ancestor.height();
+                if let Some(max_reorg_depth) = self.max_reorg_depth {
+                    if depth > max_reorg_depth {
+                        let limit = max_reorg_depth;
+                        return Err(ErrorKind::ReorgTooDeep { depth, limit }.into());
+                    }
+                }
+                // Finalized blocks can't be reverted.
+                if let Some(prev_finalized_head) = prev_finalized_head {
+                    if common_ancestor.height() < prev_finalized_head.height {
+                        let limit = head.height - prev_finalized_head.height;
+                        return Err(ErrorKind::ReorgTooDeep { depth, limit }.into());
+                    }
+                }
+            }
             let tip = Tip::from_header(header);
 
             self.chain_store_update.save_body_head(&tip)?;
             near_metrics::set_gauge(&metrics::BLOCK_HEIGHT_HEAD, tip.height as i64);
             debug!(target: "chain", "Head updated to {} at {}", tip.last_block_hash, tip.height);
+            if let Some(finality_depth) = self.finality_depth {
+                self.update_finalized_head(header, finality_depth)?;
+            }
             Ok(Some(tip))
         } else {
             Ok(None)
         }
     }
 
+    fn get_finalized_head(&self) -> Result<Option<Tip>, Error> {
+        match self.chain_store_update.finalized_head() {
+            Ok(finalized_head) => Ok(Some(finalized_head)),
+            Err(e) => match e.kind() {
+                ErrorKind::DBNotFoundErr(_) => Ok(None),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Moves the finalized head to the highest ancestor of the new head that is at least
+    /// `finality_depth` heights below it. When the height index leads to the new head, the
+    /// ancestor is looked up there, so that the headers in between aren't read on every block.
+    fn update_finalized_head(
+        &mut self,
+        head_header: &BlockHeader,
+        finality_depth: BlockHeightDelta,
+    ) -> Result<(), Error> {
+        let genesis_height = self.chain_store_update.get_genesis_height();
+        let finalized_head = self.get_finalized_head()?;
+        let target_height =
+            std::cmp::max(head_header.height().saturating_sub(finality_depth), genesis_height);
+        if finalized_head.as_ref().map_or(false, |tip| target_height <= tip.height) {
+            return Ok(());
+        }
+        let on_height_index = self
+            .chain_store_update
+            .get_block_hash_by_height(head_header.height())
+            .map_or(false, |hash| &hash == head_header.hash());
+        let header = if on_height_index {
+            let mut height = target_height;
+            loop {
+                match self.chain_store_update.get_block_hash_by_height(height) {
+                    Ok(hash) => break self.chain_store_update.get_block_header(&hash)?.clone(),
+                    Err(e) => match e.kind() {
+                        ErrorKind::DBNotFoundErr(_) if height > genesis_height => height -= 1,
+                        _ => return Err(e),
+                    },
+                }
+            }
+        } else {
+            // The header head is on another fork.
+            let mut header = head_header.clone();
+            while header.height() > target_height {
+                header = self.chain_store_update.get_previous_header(&header)?.clone();
+            }
+            header
+        };
+        if finalized_head.map_or(true, |tip| header.height() > tip.height) {
+            self.chain_store_update.save_finalized_head(&Tip::from_header(&header))?;
+        }
+        Ok(())
+    }
+
+    /// Finds the highest block that is an ancestor of both given blocks.
+    fn find_common_ancestor(
+        &mut self,
+        hash_a: &CryptoHash,
+        hash_b: &CryptoHash,
+    ) -> Result<BlockHeader, Error> {
+        let mut header_a = self.chain_store_update.get_block_header(hash_a)?.clone();
+        let mut header_b = self.chain_store_update.get_block_header(hash_b)?.clone();
+        while header_a.hash() != header_b.hash() {
+            if header_a.height() >= header_b.height() {
+                header_a = self.chain_store_update.get_previous_header(&header_a)?.clone();
+            } else {
+                header_b = self.chain_store_update.get_previous_header(&header_b)?.clone();
+            }
+        }
+        Ok(header_a)
+    }
+
     /// Marks a block as invalid,
     fn mark_block_as_challenged(
         &mut self,
@@ -3815,6 +6040,97 @@ impl<'a> ChainUpdate<'a> {
     }
 }
 
+/// Genesis needs a state root for every shard, or a single one shared by all shards.
+fn check_genesis_state_roots(
+    state_roots: &[StateRoot],
+    num_shards: NumShards,
+) -> Result<(), Error> {
+    if state_roots.len() != 1 && state_roots.len() as NumShards != num_shards {
+        return Err(ErrorKind::Other(format!(
+            "Genesis has {} state roots for {} shards",
+            state_roots.len(),
+            num_shards
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether the error condemns every block with the same hash, so that it can be remembered in
+/// `Chain::invalid_blocks`. Only chunk contents that contradict the previous chunk extra qualify:
+/// the block hash commits to the chunk headers, but not to block or chunk signatures, so a peer
+/// can make an honest block fail any other check by tampering with what the hash leaves out.
+fn invalidates_block_hash(kind: &ErrorKind) -> bool {
+    match kind {
+        ErrorKind::InvalidStateRoot
+        | ErrorKind::InvalidOutcomesProof
+        | ErrorKind::InvalidValidatorProposals
+        | ErrorKind::InvalidGasLimit
+        | ErrorKind::InvalidGasUsed
+        | ErrorKind::InvalidBalanceBurnt
+        | ErrorKind::InvalidReceiptsProof => true,
+        _ => false,
+    }
+}
+
+/// Looks for two different known blocks at `height` produced by the same block producer,
+/// including blocks on forks. If `header` is given, only blocks equivocating with it are looked
+/// for; it doesn't have to be saved yet and is returned as the right header of the proof.
+fn find_equivocation(
+    store: &mut ChainStore,
+    runtime_adapter: &dyn RuntimeAdapter,
+    height: BlockHeight,
+    header: Option<&BlockHeader>,
+) -> Result<Option<EquivocationProof>, Error> {
+    let mut block_hashes = match store.get_all_block_hashes_by_height(height) {
+        Ok(block_hashes) => block_hashes
+            .iter()
+            .flat_map(|(epoch_id, hashes)| hashes.iter().map(move |hash| (epoch_id.clone(), *hash)))
+            .filter(|(_, hash)| header.map_or(true, |header| hash != header.hash()))
+            .collect::<Vec<_>>(),
+        Err(e) => match e.kind() {
+            ErrorKind::DBNotFoundErr(_) => return Ok(None),
+            _ => return Err(e),
+        },
+    };
+    if block_hashes.is_empty() {
+        return Ok(None);
+    }
+    // Make the returned pair deterministic.
+    block_hashes.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut producers: HashMap<AccountId, CryptoHash> = HashMap::new();
+    if let Some(header) = header {
+        producers
+            .insert(runtime_adapter.get_block_producer(header.epoch_id(), height)?, *header.hash());
+    }
+    for (epoch_id, block_hash) in block_hashes {
+        let producer = runtime_adapter.get_block_producer(&epoch_id, height)?;
+        match (producers.get(&producer), header) {
+            (Some(_), Some(header)) => {
+                return Ok(Some(EquivocationProof {
+                    left_block_header: store.get_block_header(&block_hash)?.clone(),
+                    right_block_header: header.clone(),
+                    producer,
+                }));
+            }
+            (Some(other_hash), None) => {
+                let other_hash = *other_hash;
+                return Ok(Some(EquivocationProof {
+                    left_block_header: store.get_block_header(&other_hash)?.clone(),
+                    right_block_header: store.get_block_header(&block_hash)?.clone(),
+                    producer,
+                }));
+            }
+            // With a given header, only pairs with it count.
+            (None, Some(_)) => {}
+            (None, None) => {
+                producers.insert(producer, block_hash);
+            }
+        }
+    }
+    Ok(None)
+}
+
 pub fn collect_receipts<'a, T>(receipt_proofs: T) -> Vec<Receipt>
 where
     T: IntoIterator<Item = &'a ReceiptProof>,
@@ -3829,3 +6145,353 @@ pub fn collect_receipts_from_response(
         receipt_proof_response.iter().flat_map(|ReceiptProofResponse(_, proofs)| proofs),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use borsh::BorshSerialize;
+    use cached::Cached;
+
+    use near_chain_primitives::error::{ApplyChunkFailure, ErrorKind};
+    use near_primitives::errors::StorageError;
+    use near_primitives::types::StateRoot;
+
+    use crate::test_utils::setup;
+    use crate::types::{Block, Provenance};
+
+    use super::{
+        check_genesis_state_roots, invalidates_block_hash, ChainUpdate, Orphan, OrphanBlockPool,
+        OrphanPoolConfig,
+    };
+
+    fn make_blocks(num_blocks: usize) -> Vec<Block> {
+        let (mut chain, _, signer) = setup();
+        let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+        let mut blocks = vec![Block::empty(&genesis, &*signer)];
+        for _ in 1..num_blocks {
+            let block = Block::empty(blocks.last().unwrap(), &*signer);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn add_orphans(pool: &mut OrphanBlockPool, blocks: &[Block]) {
+        for block in blocks {
+            pool.add(Orphan::new(block.clone(), Provenance::NONE, Instant::now()));
+        }
+    }
+
+    #[test]
+    fn test_expire_blocks_with_missing_chunks() {
+        let (mut chain, _, signer) = setup();
+        let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+        let stale = Block::empty(&genesis, &*signer);
+        let fresh = Block::empty(&stale, &*signer);
+        let missing_chunk = stale.chunks()[0].clone();
+        chain.set_orphan_pool_config(OrphanPoolConfig {
+            max_age_secs: 60,
+            ..OrphanPoolConfig::default()
+        });
+        for (block, added) in vec![
+            (stale.clone(), Instant::now().checked_sub(Duration::from_secs(120)).unwrap()),
+            (fresh.clone(), Instant::now()),
+        ] {
+            chain.blocks_with_missing_chunks.add_block_with_missing_chunks(
+                Orphan::new(block, Provenance::NONE, added),
+                vec![missing_chunk.chunk_hash()],
+            );
+        }
+
+        let mut expired = vec![];
+        let num_expired = chain.expire_blocks_with_missing_chunks(|hash, chunks| {
+            expired.push((hash, chunks));
+        });
+        assert_eq!(num_expired, 1);
+        assert_eq!(expired, vec![(*stale.hash(), vec![missing_chunk])]);
+        assert!(!chain.is_chunk_orphan(stale.hash()));
+        assert!(chain.is_chunk_orphan(fresh.hash()));
+    }
+
+    #[test]
+    fn test_drop_orphans_of_invalid_block() {
+        let (mut chain, _, signer) = setup();
+        let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+        let invalid = Block::empty(&genesis, &*signer);
+        let child = Block::empty(&invalid, &*signer);
+        let grandchild = Block::empty(&child, &*signer);
+        chain.invalid_blocks.cache_set(*invalid.hash(), ());
+
+        for block in vec![child, grandchild] {
+            let block_hash = *block.hash();
+            let err = chain
+                .process_block(&None, block, Provenance::NONE, |_| {}, |_| {}, |_| {})
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Orphan);
+            assert!(!chain.is_orphan(&block_hash));
+        }
+        assert_eq!(chain.orphans_len(), 0);
+    }
+
+    #[test]
+    fn test_invalidates_block_hash() {
+        assert!(invalidates_block_hash(&ErrorKind::InvalidStateRoot));
+        assert!(invalidates_block_hash(&ErrorKind::InvalidGasUsed));
+        assert!(!invalidates_block_hash(&ErrorKind::InvalidSignature));
+        assert!(!invalidates_block_hash(&ErrorKind::InvalidApprovals));
+        assert!(!invalidates_block_hash(&ErrorKind::InvalidChunk));
+    }
+
+    #[test]
+    fn test_check_genesis_state_roots() {
+        let root = StateRoot::default();
+        assert!(check_genesis_state_roots(&[root; 4], 4).is_ok());
+        assert!(check_genesis_state_roots(&[root], 4).is_ok());
+        let err = check_genesis_state_roots(&[root; 3], 4).unwrap_err();
+        assert_eq!(
+            err.kind(),
+            ErrorKind::Other("Genesis has 3 state roots for 4 shards".to_string())
+        );
+        assert!(check_genesis_state_roots(&[], 1).is_err());
+    }
+
+    #[test]
+    fn test_chunks_to_rerequest() {
+        let (mut chain, _, signer) = setup();
+        let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+        let block = Block::empty(&genesis, &*signer);
+        let missing_chunk = block.chunks()[0].clone();
+        let added = Instant::now();
+        chain.blocks_with_missing_chunks.add_block_with_missing_chunks(
+            Orphan::new(block.clone(), Provenance::NONE, added),
+            vec![missing_chunk.chunk_hash()],
+        );
+
+        // The chunks were just requested when the block started waiting.
+        assert!(chain.chunks_to_rerequest(added).is_empty());
+        let first = added + CHUNK_REREQUEST_BASE_DELAY;
+        assert_eq!(chain.chunks_to_rerequest(first), vec![(*block.hash(), vec![missing_chunk])]);
+        // The delay doubles after every request.
+        let second = first + CHUNK_REREQUEST_BASE_DELAY * 2;
+        assert!(chain.chunks_to_rerequest(second - Duration::from_millis(1)).is_empty());
+        assert_eq!(chain.chunks_to_rerequest(second).len(), 1);
+        // And is capped.
+        let mut last = second;
+        for _ in 0..10 {
+            let next = last + CHUNK_REREQUEST_MAX_DELAY;
+            assert_eq!(chain.chunks_to_rerequest(next).len(), 1);
+            last = next;
+        }
+        let capped = last + CHUNK_REREQUEST_MAX_DELAY;
+        assert!(chain.chunks_to_rerequest(capped - Duration::from_millis(1)).is_empty());
+        assert_eq!(chain.chunks_to_rerequest(capped).len(), 1);
+        last = capped;
+
+        chain.blocks_with_missing_chunks.accept_chunk(&block.chunks()[0].chunk_hash());
+        assert!(chain.chunks_to_rerequest(last + CHUNK_REREQUEST_MAX_DELAY).is_empty());
+        assert!(chain.chunk_rerequests.is_empty());
+    }
+
+    #[test]
+    fn test_orphan_pool_config_limits_size() {
+        let blocks = make_blocks(10);
+        let mut pool = OrphanBlockPool::with_config(OrphanPoolConfig {
+            max_size: 5,
+            ..OrphanPoolConfig::default()
+        });
+        add_orphans(&mut pool, &blocks);
+        assert!(pool.len() <= 5);
+        assert_eq!(pool.len() + pool.len_evicted(), 10);
+        // Highest orphans are evicted first.
+        assert!(pool.contains(blocks[0].hash()));
+        assert!(!pool.contains(blocks[9].hash()));
+    }
+
+    #[test]
+    fn test_orphan_pool_reports_evicted() {
+        let blocks = make_blocks(10);
+        let mut pool = OrphanBlockPool::with_config(OrphanPoolConfig {
+            max_size: 5,
+            ..OrphanPoolConfig::default()
+        });
+        let mut evicted = vec![];
+        for block in blocks.iter() {
+            evicted.extend(pool.add(Orphan::new(block.clone(), Provenance::NONE, Instant::now())));
+        }
+        assert_eq!(evicted.len(), pool.len_evicted());
+        for (hash, prev_hash) in evicted {
+            let block = blocks.iter().find(|block| block.hash() == &hash).unwrap();
+            assert_eq!(block.header().prev_hash(), &prev_hash);
+            assert!(!pool.contains(&hash));
+        }
+    }
+
+    #[test]
+    fn test_orphan_pool_bytes() {
+        let blocks = make_blocks(10);
+        let block_size = |block: &Block| block.try_to_vec().unwrap().len();
+        let mut pool = OrphanBlockPool::new();
+        add_orphans(&mut pool, &blocks[..4]);
+        assert_eq!(pool.bytes(), blocks[..4].iter().map(block_size).sum::<usize>());
+        pool.remove_by_prev_hash(blocks[0].header().prev_hash());
+        assert_eq!(pool.bytes(), blocks[1..4].iter().map(block_size).sum::<usize>());
+
+        // Byte budget of three blocks evicts highest orphans even below the count limit.
+        let max_bytes = blocks[..3].iter().map(block_size).max().unwrap() * 3;
+        let mut pool = OrphanBlockPool::with_config(OrphanPoolConfig {
+            max_bytes,
+            ..OrphanPoolConfig::default()
+        });
+        add_orphans(&mut pool, &blocks);
+        assert!(pool.bytes() <= max_bytes);
+        assert!(pool.len() <= 3);
+        assert_eq!(pool.len() + pool.len_evicted(), blocks.len());
+        let held_bytes = pool
+            .iter()
+            .filter_map(|(hash, _, _)| blocks.iter().find(|block| block.hash() == hash))
+            .map(block_size)
+            .sum::<usize>();
+        assert_eq!(pool.bytes(), held_bytes);
+    }
+
+    #[test]
+    fn test_orphans_added_between() {
+        let blocks = make_blocks(6);
+        let mut pool = OrphanBlockPool::new();
+        let start = Instant::now();
+        for (i, block) in blocks.iter().enumerate() {
+            pool.add(Orphan::new(
+                block.clone(),
+                Provenance::NONE,
+                start + Duration::from_secs(i as u64 * 10),
+            ));
+        }
+        let mut in_window = pool.orphans_added_between(
+            start + Duration::from_secs(15),
+            start + Duration::from_secs(40),
+        );
+        in_window.sort();
+        let mut expected = blocks[2..5].iter().map(|block| *block.hash()).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(in_window, expected);
+        assert_eq!(pool.orphans_added_between(start, start).len(), 1);
+        assert!(pool
+            .orphans_added_between(start + Duration::from_secs(51), start + Duration::from_secs(60))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_remove_by_prev_hash_with_forks_at_same_height() {
+        let (_, _, signer) = setup();
+        let blocks = make_blocks(2);
+        let child_a = Block::empty_with_height(&blocks[0], 3, &*signer);
+        let child_b = Block::empty_with_height(&blocks[1], 3, &*signer);
+        let mut pool = OrphanBlockPool::new();
+        add_orphans(&mut pool, &[child_a.clone(), child_b.clone()]);
+
+        let removed = pool.remove_by_prev_hash(blocks[1].hash()).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].block.hash(), child_b.hash());
+        assert!(pool.contains(child_a.hash()));
+        assert_eq!(pool.height_idx.get(&3), Some(&vec![*child_a.hash()]));
+        assert!(pool.remove_by_prev_hash(blocks[1].hash()).is_none());
+    }
+
+    #[test]
+    fn test_orphans_by_provenance() {
+        let blocks = make_blocks(6);
+        let mut pool = OrphanBlockPool::new();
+        let provenances = [
+            Provenance::NONE,
+            Provenance::SYNC,
+            Provenance::SYNC,
+            Provenance::PRODUCED,
+            Provenance::SYNC,
+            Provenance::NONE,
+        ];
+        for (block, provenance) in blocks.iter().zip(provenances.iter()) {
+            pool.add(Orphan::new(block.clone(), provenance.clone(), Instant::now()));
+        }
+        assert_eq!(pool.orphans_by_provenance(), (2, 3, 1));
+    }
+
+    #[test]
+    fn test_orphan_pool_iter() {
+        let blocks = make_blocks(3);
+        let mut pool = OrphanBlockPool::new();
+        add_orphans(&mut pool, &blocks);
+        let mut orphans = pool
+            .iter()
+            .map(|(hash, height, provenance)| (*hash, height, provenance.clone()))
+            .collect::<Vec<_>>();
+        orphans.sort_by_key(|(_, height, _)| *height);
+        let expected = blocks
+            .iter()
+            .map(|block| (*block.hash(), block.header().height(), Provenance::NONE))
+            .collect::<Vec<_>>();
+        assert_eq!(orphans, expected);
+    }
+
+    #[test]
+    fn test_orphan_pool_compact() {
+        let blocks = make_blocks(20);
+        let mut pool = OrphanBlockPool::new();
+        add_orphans(&mut pool, &blocks);
+        for block in blocks.iter().step_by(2) {
+            pool.remove_by_prev_hash(block.header().prev_hash());
+        }
+        assert_eq!(pool.len(), 10);
+        pool.compact();
+        assert_eq!(pool.len(), 10);
+        assert_eq!(pool.height_idx.len(), 10);
+        assert_eq!(pool.prev_hash_idx.len(), 10);
+        for hashes in pool.height_idx.values().chain(pool.prev_hash_idx.values()) {
+            assert!(!hashes.is_empty());
+            assert!(hashes.iter().all(|hash| pool.contains(hash)));
+        }
+    }
+
+    #[test]
+    fn test_orphan_pool_most_awaited_parent() {
+        let blocks = make_blocks(3);
+        let mut pool = OrphanBlockPool::new();
+        assert_eq!(pool.most_awaited_parent(), None);
+        add_orphans(&mut pool, &blocks);
+        let (_, _, signer) = setup();
+        let siblings = vec![Block::empty(&blocks[1], &*signer), Block::empty(&blocks[1], &*signer)];
+        add_orphans(&mut pool, &siblings);
+        assert_eq!(pool.most_awaited_parent(), Some((*blocks[1].hash(), 3)));
+        pool.remove(blocks[2].hash());
+        pool.remove(siblings[0].hash());
+        let (_, count) = pool.most_awaited_parent().unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_runtime_apply_failed() {
+        let classify = |kind: ErrorKind| ChainUpdate::runtime_apply_failed(kind.into());
+
+        let err = classify(ErrorKind::StorageError(StorageError::StorageInternalError));
+        assert_eq!(
+            err.kind(),
+            ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::StorageError(
+                StorageError::StorageInternalError
+            ))
+        );
+        assert!(!err.is_bad_data());
+
+        let err = classify(ErrorKind::InvalidTransactions);
+        match err.kind() {
+            ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::InvalidChunk(_)) => {}
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+        assert!(err.is_bad_data());
+
+        let err = classify(ErrorKind::ValidatorError("unknown epoch".to_string()));
+        match err.kind() {
+            ErrorKind::RuntimeApplyFailed(ApplyChunkFailure::Other(_)) => {}
+            kind => panic!("Unexpected error {:?}", kind),
+        }
+        assert!(!err.is_bad_data());
+    }
+}
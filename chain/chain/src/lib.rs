@@ -1,7 +1,10 @@
 #[macro_use]
 extern crate lazy_static;
 
-pub use chain::{collect_receipts, Chain, MAX_ORPHAN_SIZE};
+pub use chain::{
+    collect_receipts, AncestorIter, Chain, ChainMetrics, GcPolicy, GcReport, HeadGuard,
+    IntegrityIssue, OrphanPoolConfig, StagedBlock, MAX_COMMON_HEADER_LOOKUPS, MAX_ORPHAN_SIZE,
+};
 pub use doomslug::{Doomslug, DoomslugBlockProductionReadiness, DoomslugThresholdMode};
 pub use lightclient::{create_light_client_block_view, get_epoch_block_producers_view};
 pub use near_chain_primitives::{self, Error, ErrorKind};
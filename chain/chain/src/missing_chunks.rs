@@ -70,6 +70,13 @@ impl<Block: BlockLike> MissingChunksPool<Block> {
         self.blocks_waiting_for_chunks.len()
     }
 
+    /// Iterates over blocks still waiting for chunks, together with the chunks they miss.
+    pub fn waiting_blocks(&self) -> impl Iterator<Item = (&Block, Option<&HashSet<ChunkHash>>)> {
+        self.blocks_waiting_for_chunks
+            .iter()
+            .map(move |(block_hash, block)| (block, self.blocks_missing_chunks.get(block_hash)))
+    }
+
     pub fn ready_blocks(&mut self) -> Vec<Block> {
         if self.blocks_ready_to_process.is_empty() {
             return Vec::new();
@@ -158,26 +165,52 @@ impl<Block: BlockLike> MissingChunksPool<Block> {
         let heights_to_remove: Vec<BlockHeight> =
             self.height_idx.keys().copied().take_while(|h| *h < height).collect();
         for h in heights_to_remove {
-            if let Some(block_hashes) = self.height_idx.remove(&h) {
+            if let Some(block_hashes) = self.height_idx.get(&h).cloned() {
                 for block_hash in block_hashes {
-                    self.blocks_waiting_for_chunks.remove(&block_hash);
-                    if let Some(chunk_hashes) = self.blocks_missing_chunks.remove(&block_hash) {
-                        for chunk_hash in chunk_hashes {
-                            if let hash_map::Entry::Occupied(mut entry) =
-                                self.missing_chunks.entry(chunk_hash)
-                            {
-                                let blocks_for_chunk = entry.get_mut();
-                                blocks_for_chunk.remove(&block_hash);
-                                if blocks_for_chunk.is_empty() {
-                                    entry.remove_entry();
-                                }
-                            }
-                        }
-                    }
+                    self.remove_block(&block_hash);
                 }
             }
         }
     }
+
+    /// Removes blocks still waiting for chunks that match the predicate. Returns them together
+    /// with the chunks they are missing.
+    pub fn remove_blocks<F>(&mut self, mut predicate: F) -> Vec<(Block, HashSet<ChunkHash>)>
+    where
+        F: FnMut(&Block) -> bool,
+    {
+        let block_hashes: Vec<BlockHash> = self
+            .blocks_waiting_for_chunks
+            .iter()
+            .filter(|(_, block)| predicate(block))
+            .map(|(block_hash, _)| *block_hash)
+            .collect();
+        block_hashes.iter().filter_map(|block_hash| self.remove_block(block_hash)).collect()
+    }
+
+    fn remove_block(&mut self, block_hash: &BlockHash) -> Option<(Block, HashSet<ChunkHash>)> {
+        let block = self.blocks_waiting_for_chunks.remove(block_hash)?;
+        if let btree_map::Entry::Occupied(mut entry) = self.height_idx.entry(block.height()) {
+            let blocks_at_height = entry.get_mut();
+            blocks_at_height.remove(block_hash);
+            if blocks_at_height.is_empty() {
+                entry.remove_entry();
+            }
+        }
+        let chunk_hashes = self.blocks_missing_chunks.remove(block_hash).unwrap_or_default();
+        for chunk_hash in chunk_hashes.iter() {
+            if let hash_map::Entry::Occupied(mut entry) =
+                self.missing_chunks.entry(chunk_hash.clone())
+            {
+                let blocks_for_chunk = entry.get_mut();
+                blocks_for_chunk.remove(block_hash);
+                if blocks_for_chunk.is_empty() {
+                    entry.remove_entry();
+                }
+            }
+        }
+        Some((block, chunk_hashes))
+    }
 }
 
 #[cfg(test)]
@@ -285,4 +318,34 @@ mod test {
         assert!(!pool.contains(&early_block_hash));
         assert!(pool.contains(&later_block_hash));
     }
+
+    #[test]
+    fn should_return_missing_chunks_of_removed_blocks() {
+        let mut pool: MissingChunksPool<MockBlock> = MissingChunksPool::default();
+        let blocks: Vec<MockBlock> = (0..3).map(MockBlock::new).collect();
+        for block in blocks.iter() {
+            let chunk_hashes = vec![get_chunk_hash(100 + block.height), get_chunk_hash(200)];
+            pool.add_block_with_missing_chunks(*block, chunk_hashes);
+        }
+        pool.accept_chunk(&get_chunk_hash(100));
+
+        let removed = pool.remove_blocks(|block| block.height < 2);
+        let mut removed_heights = removed.iter().map(|(block, _)| block.height).collect::<Vec<_>>();
+        removed_heights.sort();
+        assert_eq!(removed_heights, vec![0, 1]);
+        for (block, chunk_hashes) in removed {
+            let mut expected = vec![get_chunk_hash(200)];
+            if block.height == 1 {
+                expected.push(get_chunk_hash(101));
+            }
+            assert_eq!(chunk_hashes, expected.into_iter().collect());
+        }
+        assert!(!pool.contains(&blocks[0].hash));
+        assert!(pool.contains(&blocks[2].hash));
+
+        // Chunks of removed blocks no longer make them ready.
+        pool.accept_chunk(&get_chunk_hash(200));
+        pool.accept_chunk(&get_chunk_hash(102));
+        assert_eq!(pool.ready_blocks(), vec![blocks[2]]);
+    }
 }
@@ -2,6 +2,7 @@ use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::io;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use borsh::{BorshDeserialize, BorshSerialize};
@@ -39,17 +40,17 @@ use near_store::{
     ColBlocksToCatchup, ColChallengedBlocks, ColChunkExtra, ColChunkHashesByHeight,
     ColChunkPerHeightShard, ColChunks, ColEpochLightClientBlocks, ColGCCount,
     ColHeaderHashesByHeight, ColIncomingReceipts, ColInvalidChunks, ColLastBlockWithNewChunk,
-    ColNextBlockHashes, ColNextBlockWithNewChunk, ColOutcomeIds, ColOutgoingReceipts,
+    ColNextBlockHashes, ColNextBlockWithNewChunk, ColOrphans, ColOutcomeIds, ColOutgoingReceipts,
     ColPartialChunks, ColProcessedBlockHeights, ColReceiptIdToShardId, ColReceipts, ColState,
     ColStateChanges, ColStateDlInfos, ColStateHeaders, ColStateParts, ColTransactionResult,
     ColTransactions, ColTrieChanges, DBCol, KeyForStateChanges, ShardTries, Store, StoreUpdate,
-    TrieChanges, WrappedTrieChanges, CHUNK_TAIL_KEY, FINAL_HEAD_KEY, FORK_TAIL_KEY,
-    HEADER_HEAD_KEY, HEAD_KEY, LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY, SHOULD_COL_GC,
-    TAIL_KEY,
+    TrieChanges, WrappedTrieChanges, CHUNK_TAIL_KEY, FINALIZED_HEAD_KEY, FINAL_HEAD_KEY,
+    FORK_TAIL_KEY, HEADER_HEAD_KEY, HEAD_KEY, LARGEST_TARGET_HEIGHT_KEY, LATEST_KNOWN_KEY,
+    SHOULD_COL_GC, TAIL_KEY,
 };
 
 use crate::byzantine_assert;
-use crate::types::{Block, BlockHeader, LatestKnown};
+use crate::types::{Block, BlockHeader, LatestKnown, Provenance};
 
 /// lru cache size
 #[cfg(not(feature = "no_cache"))]
@@ -94,6 +95,8 @@ pub trait ChainStoreAccess {
     fn head_header(&mut self) -> Result<&BlockHeader, Error>;
     /// The chain final head. It is guaranteed to be monotonically increasing.
     fn final_head(&self) -> Result<Tip, Error>;
+    /// Head of blocks buried at least `finality_depth` heights under the head.
+    fn finalized_head(&self) -> Result<Tip, Error>;
     /// Larget approval target height sent by us
     fn largest_target_height(&self) -> Result<BlockHeight, Error>;
     /// Get full block.
@@ -312,6 +315,8 @@ pub struct ChainStore {
     headers: SizedCache<Vec<u8>, BlockHeader>,
     /// Cache with blocks.
     blocks: SizedCache<Vec<u8>, Block>,
+    /// Cache with sizes of serialized blocks.
+    block_sizes: SizedCache<Vec<u8>, usize>,
     /// Cache with chunks
     chunks: SizedCache<Vec<u8>, ShardChunk>,
     /// Cache with partial chunks
@@ -378,6 +383,7 @@ impl ChainStore {
             head: None,
             tail: None,
             blocks: SizedCache::with_size(CACHE_SIZE),
+            block_sizes: SizedCache::with_size(CACHE_SIZE),
             headers: SizedCache::with_size(CACHE_SIZE),
             chunks: SizedCache::with_size(CHUNK_CACHE_SIZE),
             partial_chunks: SizedCache::with_size(CHUNK_CACHE_SIZE),
@@ -513,6 +519,15 @@ impl ChainStore {
 }
 
 impl ChainStore {
+    /// Returns orphan blocks saved by `ChainStoreUpdate::add_orphan`, with their provenance.
+    pub fn iterate_orphans(&self) -> Result<Vec<(Block, Provenance)>, Error> {
+        let mut orphans = vec![];
+        for (_, value) in self.store.iter(ColOrphans) {
+            orphans.push(<(Block, Provenance)>::try_from_slice(value.as_ref())?);
+        }
+        Ok(orphans)
+    }
+
     /// Returns all outcomes generated by applying transaction or receipt with the given id.
     pub fn get_outcomes_by_id(
         &self,
@@ -549,6 +564,20 @@ impl ChainStore {
         )
     }
 
+    /// Returns the size of the borsh-serialized block. Sizes of blocks saved through this store
+    /// are recorded when the block is written, otherwise the stored bytes are read once.
+    pub fn get_block_size(&mut self, h: &CryptoHash) -> Result<usize, Error> {
+        if let Some(size) = self.block_sizes.cache_get(&h.as_ref().to_vec()) {
+            return Ok(*size);
+        }
+        let size = option_to_not_found(
+            self.store.get(ColBlock, h.as_ref()).map(|bytes| bytes.map(|bytes| bytes.len())),
+            &format!("BLOCK: {}", h),
+        )?;
+        self.block_sizes.cache_set(h.as_ref().to_vec(), size);
+        Ok(size)
+    }
+
     /// Returns a HashSet of Chunk Hashes for current Height
     pub fn get_all_chunk_hashes_by_height(
         &mut self,
@@ -797,6 +826,10 @@ impl ChainStoreAccess for ChainStore {
         option_to_not_found(self.store.get_ser(ColBlockMisc, FINAL_HEAD_KEY), "FINAL HEAD")
     }
 
+    fn finalized_head(&self) -> Result<Tip, Error> {
+        option_to_not_found(self.store.get_ser(ColBlockMisc, FINALIZED_HEAD_KEY), "FINALIZED HEAD")
+    }
+
     /// Get full block.
     fn get_block(&mut self, h: &CryptoHash) -> Result<&Block, Error> {
         option_to_not_found(
@@ -1105,6 +1138,7 @@ impl ChainStoreAccess for ChainStore {
 #[derive(Default)]
 struct ChainStoreCacheUpdate {
     blocks: HashMap<CryptoHash, Block>,
+    block_sizes: HashMap<CryptoHash, usize>,
     headers: HashMap<CryptoHash, BlockHeader>,
     block_extras: HashMap<CryptoHash, BlockExtra>,
     chunk_extras: HashMap<(CryptoHash, ShardId), ChunkExtra>,
@@ -1147,7 +1181,10 @@ pub struct ChainStoreUpdate<'a> {
     fork_tail: Option<BlockHeight>,
     header_head: Option<Tip>,
     final_head: Option<Tip>,
+    finalized_head: Option<Tip>,
     largest_target_height: Option<BlockHeight>,
+    add_orphans: Vec<(Block, Provenance)>,
+    remove_orphans: Vec<CryptoHash>,
     trie_changes: Vec<WrappedTrieChanges>,
     add_blocks_to_catchup: Vec<(CryptoHash, CryptoHash)>,
     // A pair (prev_hash, hash) to be removed from blocks to catchup
@@ -1171,7 +1208,10 @@ impl<'a> ChainStoreUpdate<'a> {
             fork_tail: None,
             header_head: None,
             final_head: None,
+            finalized_head: None,
             largest_target_height: None,
+            add_orphans: vec![],
+            remove_orphans: vec![],
             trie_changes: vec![],
             add_blocks_to_catchup: vec![],
             remove_blocks_to_catchup: vec![],
@@ -1283,6 +1323,14 @@ impl<'a> ChainStoreAccess for ChainStoreUpdate<'a> {
         }
     }
 
+    fn finalized_head(&self) -> Result<Tip, Error> {
+        if let Some(finalized_head) = self.finalized_head.as_ref() {
+            Ok(finalized_head.clone())
+        } else {
+            self.chain_store.finalized_head()
+        }
+    }
+
     fn largest_target_height(&self) -> Result<BlockHeight, Error> {
         if let Some(largest_target_height) = &self.largest_target_height {
             Ok(largest_target_height.clone())
@@ -1600,6 +1648,27 @@ impl<'a> ChainStoreUpdate<'a> {
         Ok(())
     }
 
+    pub fn save_finalized_head(&mut self, t: &Tip) -> Result<(), Error> {
+        self.finalized_head = Some(t.clone());
+        Ok(())
+    }
+
+    /// Removes the canonical height to block hash entries for heights in `heights`.
+    pub fn clear_block_hashes_by_height(&mut self, heights: RangeInclusive<BlockHeight>) {
+        for height in heights {
+            self.chain_store_cache_update.height_to_hashes.insert(height, None);
+        }
+    }
+
+    /// Keeps the orphan block across restarts.
+    pub fn add_orphan(&mut self, block: Block, provenance: Provenance) {
+        self.add_orphans.push((block, provenance));
+    }
+
+    pub fn remove_orphan(&mut self, hash: CryptoHash) {
+        self.remove_orphans.push(hash);
+    }
+
     fn update_height_if_not_challenged(
         &mut self,
         height: BlockHeight,
@@ -1792,12 +1861,16 @@ impl<'a> ChainStoreUpdate<'a> {
             .insert(epoch_hash.clone(), light_client_block);
     }
 
+    /// Saves receipts produced by applying the chunk of `shard_id`. Receipts are stored ordered
+    /// by the id of the receiving shard, so the stored list doesn't depend on `HashMap` order.
     pub fn save_outgoing_receipt(
         &mut self,
         hash: &CryptoHash,
         shard_id: ShardId,
         receipt_result: ReceiptResult,
     ) {
+        let mut receipt_result = receipt_result.into_iter().collect::<Vec<_>>();
+        receipt_result.sort_by_key(|(receipt_shard_id, _)| *receipt_shard_id);
         let mut outgoing_receipts = Vec::new();
         for (receipt_shard_id, receipts) in receipt_result {
             for receipt in receipts {
@@ -1960,14 +2033,11 @@ impl<'a> ChainStoreUpdate<'a> {
         for height in chunk_tail..min_chunk_height {
             let chunk_hashes = self.chain_store.get_all_chunk_hashes_by_height(height)?;
             for chunk_hash in chunk_hashes {
-                // 1. Delete chunk-related data
-                let chunk = self.get_chunk(&chunk_hash)?.clone();
-                debug_assert_eq!(chunk.cloned_header().height_created(), height);
-                for transaction in chunk.transactions() {
-                    self.gc_col(ColTransactions, &transaction.get_hash().into());
-                }
-                for receipt in chunk.receipts() {
-                    self.gc_col(ColReceipts, &receipt.get_hash().into());
+                // 1. Delete chunk-related data, unless the chunk was pruned before.
+                let chunk = self.get_chunk(&chunk_hash).ok().cloned();
+                if let Some(chunk) = chunk {
+                    debug_assert_eq!(chunk.cloned_header().height_created(), height);
+                    self.gc_chunk_contents(&chunk);
                 }
 
                 // 2. Delete chunk_hash-indexed data
@@ -2054,14 +2124,13 @@ impl<'a> ChainStoreUpdate<'a> {
             }
         }
 
-        let block = self
-            .get_block(&block_hash)
-            .expect("block data is not expected to be already cleaned")
-            .clone();
-        let height = block.header().height();
+        let header = self.get_block_header(&block_hash)?.clone();
+        // The body is missing if it was removed by `Chain::prune_blocks`.
+        let block = self.get_block(&block_hash).ok().cloned();
+        let height = header.height();
 
         // 2. Delete shard_id-indexed data (Receipts, State Headers and Parts, etc.)
-        for shard_id in 0..block.header().chunk_mask().len() as ShardId {
+        for shard_id in 0..header.chunk_mask().len() as ShardId {
             let block_shard_id = get_block_shard_id(&block_hash, shard_id);
             self.gc_outgoing_receipts(&block_hash, shard_id);
             self.gc_col(ColIncomingReceipts, &block_shard_id);
@@ -2106,7 +2175,7 @@ impl<'a> ChainStoreUpdate<'a> {
             self.gc_col(ColStateChanges, &key);
         }
         self.gc_col(ColBlockRefCount, &block_hash_vec);
-        self.gc_outcomes(&block)?;
+        self.gc_outcomes(&header)?;
         match gc_mode {
             GCMode::StateSync { clear_block_info: false } => {}
             _ => self.gc_col(ColBlockInfo, &block_hash_vec),
@@ -2114,23 +2183,27 @@ impl<'a> ChainStoreUpdate<'a> {
         self.gc_col(ColStateDlInfos, &block_hash_vec);
 
         // 4. Update or delete block_hash_per_height
-        self.gc_col_block_per_height(&block_hash, height, &block.header().epoch_id())?;
+        self.gc_col_block_per_height(&block_hash, height, &header.epoch_id())?;
 
         match gc_mode {
             GCMode::Fork(_) => {
                 // 5. Forks only clearing
-                self.dec_block_refcount(block.header().prev_hash())?;
+                self.dec_block_refcount(header.prev_hash())?;
             }
             GCMode::Canonical(_) => {
                 // 6. Canonical Chain only clearing
                 // Delete chunks, chunk-indexed data and block headers
-                let mut min_chunk_height = self.tail()?;
-                for chunk_header in block.chunks().iter() {
-                    if min_chunk_height > chunk_header.height_created() {
-                        min_chunk_height = chunk_header.height_created();
+                // Without the body it's unknown which older chunks the block still refers to,
+                // so chunks are cleared together with the next block that has its body.
+                if let Some(block) = block {
+                    let mut min_chunk_height = self.tail()?;
+                    for chunk_header in block.chunks().iter() {
+                        if min_chunk_height > chunk_header.height_created() {
+                            min_chunk_height = chunk_header.height_created();
+                        }
                     }
+                    self.clear_chunk_data_and_headers(min_chunk_height)?;
                 }
-                self.clear_chunk_data_and_headers(min_chunk_height)?;
             }
             GCMode::StateSync { .. } => {
                 // 7. State Sync clearing
@@ -2195,6 +2268,29 @@ impl<'a> ChainStoreUpdate<'a> {
         Ok(())
     }
 
+    fn gc_chunk_contents(&mut self, chunk: &ShardChunk) {
+        for transaction in chunk.transactions() {
+            self.gc_col(ColTransactions, &transaction.get_hash().into());
+        }
+        for receipt in chunk.receipts() {
+            self.gc_col(ColReceipts, &receipt.get_hash().into());
+        }
+    }
+
+    /// Deletes the body of the block, keeping its header.
+    pub fn delete_block_body(&mut self, block_hash: &CryptoHash) {
+        self.gc_col(ColBlock, &block_hash.as_ref().into());
+    }
+
+    /// Deletes the chunk together with its transactions and receipts. Chunk headers stay
+    /// available in block headers and partial chunks.
+    pub fn delete_chunk_body(&mut self, chunk_hash: &ChunkHash) -> Result<(), Error> {
+        let chunk = self.get_chunk(chunk_hash)?.clone();
+        self.gc_chunk_contents(&chunk);
+        self.gc_col(ColChunks, &chunk_hash.clone().into());
+        Ok(())
+    }
+
     pub fn gc_outgoing_receipts(&mut self, block_hash: &CryptoHash, shard_id: ShardId) {
         let mut store_update = self.store().store_update();
         match self.get_outgoing_receipts(block_hash, shard_id).map(|receipts| {
@@ -2228,13 +2324,18 @@ impl<'a> ChainStoreUpdate<'a> {
         self.merge(store_update);
     }
 
-    pub fn gc_outcomes(&mut self, block: &Block) -> Result<(), Error> {
-        let block_hash = block.hash();
+    pub fn gc_outcomes(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        let block_hash = header.hash();
         let mut store_update = self.store().store_update();
-        for chunk_header in
-            block.chunks().iter().filter(|h| h.height_included() == block.header().height())
-        {
-            let shard_id = chunk_header.shard_id();
+        // Outcomes are only saved for chunks included in the block.
+        let shard_ids = header
+            .chunk_mask()
+            .iter()
+            .enumerate()
+            .filter(|(_, included)| **included)
+            .map(|(shard_id, _)| shard_id as ShardId)
+            .collect::<Vec<_>>();
+        for shard_id in shard_ids {
             let outcome_ids =
                 self.chain_store.get_outcomes_by_block_hash_and_shard_id(block_hash, shard_id)?;
             for outcome_id in outcome_ids {
@@ -2287,6 +2388,7 @@ impl<'a> ChainStoreUpdate<'a> {
             DBCol::ColBlock => {
                 store_update.delete(col, key);
                 self.chain_store.blocks.cache_remove(key);
+                self.chain_store.block_sizes.cache_remove(key);
             }
             DBCol::ColBlockExtra => {
                 store_update.delete(col, key);
@@ -2390,7 +2492,8 @@ impl<'a> ChainStoreUpdate<'a> {
             | DBCol::ColEpochValidatorInfo
             | DBCol::ColBlockOrdinal
             | DBCol::_ColTransactionRefCount
-            | DBCol::ColCachedContractCode => {
+            | DBCol::ColCachedContractCode
+            | DBCol::ColOrphans => {
                 unreachable!();
             }
         }
@@ -2422,18 +2525,27 @@ impl<'a> ChainStoreUpdate<'a> {
         Self::write_col_misc(&mut store_update, FORK_TAIL_KEY, &mut self.fork_tail)?;
         Self::write_col_misc(&mut store_update, HEADER_HEAD_KEY, &mut self.header_head)?;
         Self::write_col_misc(&mut store_update, FINAL_HEAD_KEY, &mut self.final_head)?;
+        Self::write_col_misc(&mut store_update, FINALIZED_HEAD_KEY, &mut self.finalized_head)?;
         Self::write_col_misc(
             &mut store_update,
             LARGEST_TARGET_HEIGHT_KEY,
             &mut self.largest_target_height,
         )?;
-        debug_assert!(self.chain_store_cache_update.blocks.len() <= 1);
         for (hash, block) in self.chain_store_cache_update.blocks.iter() {
-            let mut map =
-                match self.chain_store.get_all_block_hashes_by_height(block.header().height()) {
-                    Ok(m) => m.clone(),
-                    Err(_) => HashMap::new(),
-                };
+            // Several blocks at the same height may be saved in one update.
+            let mut map = match self
+                .chain_store_cache_update
+                .block_hash_per_height
+                .get(&block.header().height())
+            {
+                Some(m) => m.clone(),
+                None => {
+                    match self.chain_store.get_all_block_hashes_by_height(block.header().height()) {
+                        Ok(m) => m.clone(),
+                        Err(_) => HashMap::new(),
+                    }
+                }
+            };
             map.entry(block.header().epoch_id().clone())
                 .or_insert_with(|| HashSet::new())
                 .insert(*hash);
@@ -2445,10 +2557,11 @@ impl<'a> ChainStoreUpdate<'a> {
             self.chain_store_cache_update
                 .block_hash_per_height
                 .insert(block.header().height(), map);
-            store_update.set_ser(ColBlock, hash.as_ref(), block)?;
+            let bytes = block.try_to_vec()?;
+            store_update.set(ColBlock, hash.as_ref(), &bytes);
+            self.chain_store_cache_update.block_sizes.insert(*hash, bytes.len());
         }
         let mut header_hashes_by_height: HashMap<BlockHeight, HashSet<CryptoHash>> = HashMap::new();
-        debug_assert!(self.chain_store_cache_update.headers.len() <= 1);
         for (hash, header) in self.chain_store_cache_update.headers.iter() {
             if self.chain_store.get_block_header(hash).is_ok() {
                 // No need to add same Header once again
@@ -2691,6 +2804,12 @@ impl<'a> ChainStoreUpdate<'a> {
         for hash in self.remove_state_dl_infos.drain(..) {
             store_update.delete(ColStateDlInfos, hash.as_ref());
         }
+        for hash in self.remove_orphans.drain(..) {
+            store_update.delete(ColOrphans, hash.as_ref());
+        }
+        for (block, provenance) in self.add_orphans.drain(..) {
+            store_update.set_ser(ColOrphans, block.hash().as_ref(), &(block, provenance))?;
+        }
         for hash in self.challenged_blocks.drain() {
             store_update.set_ser(ColChallengedBlocks, hash.as_ref(), &true)?;
         }
@@ -2724,6 +2843,7 @@ impl<'a> ChainStoreUpdate<'a> {
         store_update.commit()?;
         let ChainStoreCacheUpdate {
             blocks,
+            block_sizes,
             headers,
             block_extras,
             chunk_extras,
@@ -2753,6 +2873,9 @@ impl<'a> ChainStoreUpdate<'a> {
         for (hash, block) in blocks {
             self.chain_store.blocks.cache_set(hash.into(), block);
         }
+        for (hash, size) in block_sizes {
+            self.chain_store.block_sizes.cache_set(hash.into(), size);
+        }
         for (hash, header) in headers {
             self.chain_store.headers.cache_set(hash.into(), header);
         }
@@ -2853,7 +2976,9 @@ impl<'a> ChainStoreUpdate<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use borsh::BorshSerialize;
     use cached::Cached;
@@ -2865,6 +2990,7 @@ mod tests {
     use near_primitives::epoch_manager::block_info::BlockInfo;
     use near_primitives::errors::InvalidTxError;
     use near_primitives::hash::hash;
+    use near_primitives::receipt::Receipt;
     use near_primitives::types::{BlockHeight, EpochId, GCCount, NumBlocks};
     use near_primitives::utils::index_to_bytes;
     use near_primitives::validator_signer::InMemoryValidatorSigner;
@@ -2875,7 +3001,7 @@ mod tests {
 
     use crate::store::{ChainStoreAccess, GCMode};
     use crate::test_utils::KeyValueRuntime;
-    use crate::{Chain, ChainGenesis, DoomslugThresholdMode};
+    use crate::{Chain, ChainGenesis, DoomslugThresholdMode, GcPolicy};
 
     fn get_chain() -> Chain {
         get_chain_with_epoch_length(10)
@@ -2898,6 +3024,71 @@ mod tests {
         Chain::new(runtime_adapter, &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap()
     }
 
+    /// Saves a chain of empty blocks on top of genesis directly through the store, bypassing
+    /// block processing. Returns all blocks including genesis.
+    fn save_empty_blocks(chain: &mut Chain, num_blocks: NumBlocks) -> Vec<Block> {
+        let genesis = chain.get_block_by_height(0).unwrap().clone();
+        let signer =
+            Arc::new(InMemoryValidatorSigner::from_seed("test1", KeyType::ED25519, "test1"));
+        let mut prev_block = genesis.clone();
+        let mut blocks = vec![prev_block.clone()];
+        for i in 1..num_blocks {
+            let block = Block::empty_with_height(&prev_block, i, &*signer.clone());
+            blocks.push(block.clone());
+            let mut store_update = chain.mut_store().store_update();
+            store_update.save_block(block.clone());
+            store_update.inc_block_refcount(block.header().prev_hash()).unwrap();
+            store_update.save_head(&Tip::from_header(block.header())).unwrap();
+            store_update.save_block_header(block.header().clone()).unwrap();
+            store_update
+                .chain_store_cache_update
+                .height_to_hashes
+                .insert(i, Some(*block.header().hash()));
+            store_update.save_next_block_hash(&prev_block.hash(), *block.hash());
+            store_update.commit().unwrap();
+
+            prev_block = block.clone();
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_genesis_receipts() {
+        let store = create_test_store();
+        let runtime_adapter = Arc::new(KeyValueRuntime::new(store));
+        let receipts = vec![Receipt::new_balance_refund(&"test1".to_string(), 100)];
+        let mut chain_genesis = ChainGenesis::test();
+        chain_genesis.genesis_receipts.insert(0, receipts.clone());
+        let mut chain =
+            Chain::new(runtime_adapter, &chain_genesis, DoomslugThresholdMode::NoApprovals)
+                .unwrap();
+        let genesis_hash = *chain.genesis().hash();
+        let receipt_proofs = chain.mut_store().get_incoming_receipts(&genesis_hash, 0).unwrap();
+        assert_eq!(receipt_proofs.len(), 1);
+        assert_eq!(receipt_proofs[0].0, receipts);
+    }
+
+    #[test]
+    fn test_outgoing_receipts_order() {
+        let mut chain = get_chain();
+        let block_hash = hash(&[1]);
+        let receipts = (0..8)
+            .map(|i| Receipt::new_balance_refund(&format!("test{}", i), 100))
+            .collect::<Vec<_>>();
+        for _ in 0..10 {
+            // Every `HashMap` gets its own random seed, so iteration order differs between runs.
+            let receipt_result = receipts
+                .iter()
+                .enumerate()
+                .map(|(i, receipt)| (i as u64, vec![receipt.clone()]))
+                .collect::<HashMap<_, _>>();
+            let mut store_update = chain.mut_store().store_update();
+            store_update.save_outgoing_receipt(&block_hash, 0, receipt_result);
+            assert_eq!(store_update.get_outgoing_receipts(&block_hash, 0).unwrap(), &receipts);
+            store_update.commit().unwrap();
+        }
+    }
+
     #[test]
     fn test_tx_validity_long_fork() {
         let transaction_validity_period = 5;
@@ -3091,33 +3282,31 @@ mod tests {
         assert_ne!(epoch_id_to_hash, epoch_id_to_hash1);
     }
 
+    /// Archival nodes must refuse to garbage collect anything.
+    #[test]
+    fn test_clear_old_data_archival() {
+        let mut chain = get_chain_with_epoch_length(1);
+        let blocks = save_empty_blocks(&mut chain, 15);
+
+        chain.epoch_length = 1;
+        chain.archival = true;
+        let trie = chain.runtime_adapter.get_tries();
+        assert!(chain.clear_data(trie.clone(), 100).is_err());
+        for block in blocks.iter() {
+            assert!(chain.get_block(&block.hash()).is_ok());
+        }
+
+        chain.archival = false;
+        assert!(chain.clear_data(trie, 100).is_ok());
+        assert!(chain.get_block(&blocks[1].hash()).is_err());
+    }
+
     /// Test that garbage collection works properly. The blocks behind gc head should be garbage
     /// collected while the blocks that are ahead of it should not.
     #[test]
     fn test_clear_old_data() {
         let mut chain = get_chain_with_epoch_length(1);
-        let genesis = chain.get_block_by_height(0).unwrap().clone();
-        let signer =
-            Arc::new(InMemoryValidatorSigner::from_seed("test1", KeyType::ED25519, "test1"));
-        let mut prev_block = genesis.clone();
-        let mut blocks = vec![prev_block.clone()];
-        for i in 1..15 {
-            let block = Block::empty_with_height(&prev_block, i, &*signer.clone());
-            blocks.push(block.clone());
-            let mut store_update = chain.mut_store().store_update();
-            store_update.save_block(block.clone());
-            store_update.inc_block_refcount(block.header().prev_hash()).unwrap();
-            store_update.save_head(&Tip::from_header(block.header())).unwrap();
-            store_update.save_block_header(block.header().clone()).unwrap();
-            store_update
-                .chain_store_cache_update
-                .height_to_hashes
-                .insert(i, Some(*block.header().hash()));
-            store_update.save_next_block_hash(&prev_block.hash(), *block.hash());
-            store_update.commit().unwrap();
-
-            prev_block = block.clone();
-        }
+        let blocks = save_empty_blocks(&mut chain, 15);
 
         chain.epoch_length = 1;
         let trie = chain.runtime_adapter.get_tries();
@@ -3187,6 +3376,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_run_gc() {
+        let mut chain = get_chain_with_epoch_length(1);
+        let blocks = save_empty_blocks(&mut chain, 15);
+
+        let policy = GcPolicy {
+            gc_blocks_limit: 100,
+            clear_forks: true,
+            max_orphan_age: Some(Duration::from_secs(0)),
+        };
+        chain.archival = true;
+        assert!(chain.run_gc(policy.clone()).is_err());
+        chain.archival = false;
+        let report = chain.run_gc(policy).unwrap();
+
+        let removed = blocks.iter().filter(|block| chain.get_block(block.hash()).is_err()).count();
+        assert!(removed > 0);
+        assert_eq!(report.fork_blocks + report.canonical_blocks, removed as NumBlocks);
+        assert_eq!(report.orphans, 0);
+        let gc_count = chain
+            .store()
+            .store
+            .get_ser::<GCCount>(
+                DBCol::ColGCCount,
+                &DBCol::ColBlock.try_to_vec().expect("Failed to serialize DBCol"),
+            )
+            .unwrap();
+        assert_eq!(gc_count, Some(removed as GCCount));
+    }
+
+    #[test]
+    fn test_prune_blocks() {
+        let mut chain = get_chain_with_epoch_length(1);
+        let blocks = save_empty_blocks(&mut chain, 15);
+
+        chain.max_reorg_depth = Some(5);
+        chain.archival = true;
+        assert!(chain.prune_blocks(12).is_err());
+        chain.archival = false;
+        // Blocks above height 9 may still be reverted by a reorg.
+        assert_eq!(chain.prune_blocks(12).unwrap(), (8, 0));
+        for block in blocks.iter() {
+            let height = block.header().height();
+            assert_eq!(chain.get_block(block.hash()).is_ok(), height == 0 || height >= 9);
+            assert!(chain.get_block_header(block.hash()).is_ok());
+        }
+        assert_eq!(chain.prune_blocks(12).unwrap(), (0, 0));
+
+        // Garbage collection still works after block bodies are gone.
+        let policy = GcPolicy { gc_blocks_limit: 100, clear_forks: true, max_orphan_age: None };
+        let report = chain.run_gc(policy).unwrap();
+        assert!(report.canonical_blocks > 0);
+    }
+
     #[test]
     fn test_clear_old_data_fixed_height() {
         let mut chain = get_chain();
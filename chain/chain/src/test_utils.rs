@@ -1,10 +1,10 @@
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use num_rational::Rational;
 use serde::Serialize;
 use tracing::debug;
@@ -45,7 +45,7 @@ use near_store::{
 use crate::chain::{Chain, NUM_EPOCHS_TO_KEEP_STORE_DATA};
 use crate::store::ChainStoreAccess;
 use crate::types::{
-    ApplyTransactionResult, BlockHeaderInfo, ChainGenesis, ValidatorInfoIdentifier,
+    ApplyTransactionResult, BlockHeaderInfo, ChainGenesis, Clock, ValidatorInfoIdentifier,
 };
 #[cfg(feature = "protocol_feature_block_header_v3")]
 use crate::Doomslug;
@@ -966,11 +966,16 @@ impl RuntimeAdapter for KeyValueRuntime {
     }
 
     fn get_epoch_start_height(&self, block_hash: &CryptoHash) -> Result<BlockHeight, Error> {
-        let epoch_id = self.get_epoch_and_valset(*block_hash)?.0;
-        match self.get_block_header(&epoch_id.0)? {
-            Some(block_header) => Ok(block_header.height()),
-            None => Ok(0),
+        let block_header = self
+            .get_block_header(block_hash)?
+            .ok_or_else(|| ErrorKind::DBNotFoundErr(to_base(block_hash)))?;
+        let prev_hash = *block_header.prev_hash();
+        if prev_hash == CryptoHash::default() {
+            return Ok(block_header.height());
         }
+        // The epoch of a block is determined by its previous block.
+        self.get_epoch_and_valset(prev_hash)?;
+        Ok(*self.epoch_start.read().unwrap().get(&prev_hash).unwrap())
     }
 
     fn get_gc_stop_height(&self, block_hash: &CryptoHash) -> BlockHeight {
@@ -1145,6 +1150,7 @@ pub fn setup_with_tx_validity_period(
             transaction_validity_period: tx_validity_period,
             epoch_length: 10,
             protocol_version: PROTOCOL_VERSION,
+            genesis_receipts: HashMap::new(),
         },
         DoomslugThresholdMode::NoApprovals,
     )
@@ -1185,6 +1191,7 @@ pub fn setup_with_validators(
             transaction_validity_period: tx_validity_period,
             epoch_length,
             protocol_version: PROTOCOL_VERSION,
+            genesis_receipts: HashMap::new(),
         },
         DoomslugThresholdMode::NoApprovals,
     )
@@ -1192,6 +1199,32 @@ pub fn setup_with_validators(
     (chain, runtime, signers)
 }
 
+/// Clock that only moves when told to, for deterministic timestamp checks.
+pub struct MockClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        MockClock { now: Mutex::new(now) }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = *now + duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
 pub fn format_hash(h: CryptoHash) -> String {
     to_base(&h)[..6].to_string()
 }
@@ -1304,6 +1337,7 @@ impl ChainGenesis {
             transaction_validity_period: 100,
             epoch_length: 5,
             protocol_version: PROTOCOL_VERSION,
+            genesis_receipts: HashMap::new(),
         }
     }
 }
@@ -12,13 +12,15 @@ use near_chain_primitives::Error;
 use near_crypto::Signature;
 use near_pool::types::PoolIterator;
 pub use near_primitives::block::{Block, BlockHeader, Tip};
-use near_primitives::challenge::{ChallengesResult, SlashedValidator};
+use near_primitives::challenge::{
+    BlockDoubleSign, ChallengeBody, ChallengesResult, SlashedValidator,
+};
 use near_primitives::checked_feature;
 use near_primitives::epoch_manager::block_info::BlockInfo;
 use near_primitives::epoch_manager::epoch_info::EpochInfo;
 use near_primitives::errors::InvalidTxError;
 use near_primitives::hash::{hash, CryptoHash};
-use near_primitives::merkle::{merklize, MerklePath};
+use near_primitives::merkle::{merklize, verify_path, MerklePath};
 use near_primitives::receipt::{Receipt, ReceiptResult};
 use near_primitives::sharding::{ChunkHash, ReceiptList, ShardChunkHeader};
 use near_primitives::transaction::{ExecutionOutcomeWithId, SignedTransaction};
@@ -60,7 +62,7 @@ impl BlockStatus {
 }
 
 /// Options for block origin.
-#[derive(Eq, PartialEq, Clone, Debug)]
+#[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, Clone, Debug)]
 pub enum Provenance {
     /// No provenance.
     NONE,
@@ -76,6 +78,106 @@ pub struct AcceptedBlock {
     pub hash: CryptoHash,
     pub status: BlockStatus,
     pub provenance: Provenance,
+    /// Blocks reverted and applied by the reorg, if the block caused one.
+    pub reorg: Option<ReorgEvent>,
+}
+
+/// Outcome of `Chain::process_block_with_result`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessBlockResult {
+    /// New head after processing the block and the orphans it unlocked, if the head changed.
+    pub new_head: Option<Tip>,
+    pub status: BlockStatus,
+    /// Number of orphans accepted because the block became known.
+    pub orphans_unlocked: usize,
+}
+
+/// Change of the canonical chain caused by a reorg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    /// Last block shared by the old and the new canonical chain.
+    pub common_ancestor: CryptoHash,
+    /// Blocks removed from the canonical chain, starting from the old head.
+    pub reverted: Vec<CryptoHash>,
+    /// Blocks added to the canonical chain, ending with the new head.
+    pub applied: Vec<CryptoHash>,
+}
+
+/// Source of the current time used to validate block timestamps.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock returning the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Notification recorded by `Chain::process_block_collect`, in the order the corresponding
+/// `process_block` callback would have been invoked.
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    /// A block was accepted. Carries the reorg it caused, if any.
+    BlockAccepted(AcceptedBlock),
+    /// A block can't be processed until these chunks are received.
+    ChunksMissing(Vec<ShardChunkHeader>),
+    Challenge(ChallengeBody),
+}
+
+/// Receives notifications about chain updates. Registered with `Chain::register_handler`; all
+/// methods default to doing nothing.
+pub trait ChainEventHandler: Send {
+    /// Called after a block was accepted into the chain.
+    fn on_block_accepted(&mut self, _block: &AcceptedBlock) {}
+
+    /// Called when a block can't be processed until the given chunks are received.
+    fn on_chunks_missing(&mut self, _chunks: &[ShardChunkHeader]) {}
+
+    /// Called when a new block switched the head to a different fork.
+    fn on_reorg(&mut self, _event: &ReorgEvent) {}
+}
+
+/// Two different blocks at the same height signed by the same block producer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EquivocationProof {
+    pub producer: AccountId,
+    pub left_block_header: BlockHeader,
+    pub right_block_header: BlockHeader,
+}
+
+impl From<EquivocationProof> for BlockDoubleSign {
+    fn from(proof: EquivocationProof) -> Self {
+        BlockDoubleSign {
+            left_block_header: proof.left_block_header.try_to_vec().expect("Failed to serialize"),
+            right_block_header: proof.right_block_header.try_to_vec().expect("Failed to serialize"),
+        }
+    }
+}
+
+/// Proof that a transaction is included in a block.
+/// `tx_proof` leads from the transaction to the chunk's `tx_root`, and `chunk_proof` leads
+/// from that `tx_root` to the block header's `chunk_tx_root`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TxInclusionProof {
+    pub block_hash: CryptoHash,
+    pub shard_id: ShardId,
+    pub chunk_hash: ChunkHash,
+    pub tx_root: CryptoHash,
+    pub tx_proof: MerklePath,
+    pub chunk_proof: MerklePath,
+}
+
+impl TxInclusionProof {
+    /// Checks the proof for `transaction` against the `chunk_tx_root` of the block header.
+    pub fn verify(&self, transaction: &SignedTransaction, chunk_tx_root: &CryptoHash) -> bool {
+        verify_path(self.tx_root, &self.tx_proof, transaction)
+            && verify_path(*chunk_tx_root, &self.chunk_proof, &self.tx_root)
+    }
 }
 
 pub struct ApplyTransactionResult {
@@ -211,6 +313,8 @@ pub struct ChainGenesis {
     pub transaction_validity_period: NumBlocks,
     pub epoch_length: BlockHeightDelta,
     pub protocol_version: ProtocolVersion,
+    /// Receipts delivered to shards at genesis, saved as incoming receipts of the genesis block.
+    pub genesis_receipts: HashMap<ShardId, Vec<Receipt>>,
 }
 
 impl<T> From<T> for ChainGenesis
@@ -230,6 +334,7 @@ where
             transaction_validity_period: genesis_config.transaction_validity_period,
             epoch_length: genesis_config.epoch_length,
             protocol_version: genesis_config.protocol_version,
+            genesis_receipts: HashMap::new(),
         }
     }
 }
@@ -1,8 +1,36 @@
-use near_chain::test_utils::setup;
-use near_chain::{Block, ChainStoreAccess, ErrorKind, Provenance};
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+use borsh::BorshSerialize;
+use chrono::{Duration, Utc};
+
+use near_chain::test_utils::{setup, setup_with_validators, KeyValueRuntime, MockClock};
+use near_chain::types::{
+    AcceptedBlock, ChainEvent, ChainEventHandler, ProcessBlockResult, ReorgEvent,
+};
+use near_chain::{
+    Block, BlockStatus, Chain, ChainGenesis, ChainStore, ChainStoreAccess, DoomslugThresholdMode,
+    ErrorKind, IntegrityIssue, OrphanPoolConfig, Provenance, MAX_COMMON_HEADER_LOOKUPS,
+};
+use near_crypto::{InMemorySigner, KeyType};
 use near_logger_utils::init_test_logger;
-use near_primitives::hash::CryptoHash;
+use near_primitives::block::Tip;
+use near_primitives::challenge::ChallengeBody;
+use near_primitives::checked_feature;
+use near_primitives::hash::{hash, CryptoHash};
+use near_primitives::merkle::{merklize, PartialMerkleTree};
+use near_primitives::receipt::Receipt;
+use near_primitives::sharding::{
+    EncodedShardChunk, ReceiptProof, ReedSolomonWrapper, ShardChunk, ShardChunkHeader, ShardProof,
+};
+use near_primitives::syncing::StatePartKey;
+use near_primitives::transaction::SignedTransaction;
+use near_primitives::types::{EpochId, Gas, StateRoot};
+use near_primitives::utils::{from_timestamp, to_timestamp};
+use near_primitives::validator_signer::{InMemoryValidatorSigner, ValidatorSigner};
 use near_primitives::version::PROTOCOL_VERSION;
+use near_store::test_utils::create_test_store;
+use near_store::DBCol;
 use num_rational::Rational;
 
 #[test]
@@ -241,3 +269,1780 @@ fn next_blocks() {
     assert_eq!(chain.mut_store().get_next_block_hash(&b1_hash).unwrap(), &b3_hash);
     assert_eq!(chain.mut_store().get_next_block_hash(&b3_hash).unwrap(), &b4_hash);
 }
+
+/// Creates a chunk with the given transactions on top of `prev`, included in the next block.
+fn create_chunk(
+    prev: &Block,
+    signer: &dyn ValidatorSigner,
+    transactions: Vec<SignedTransaction>,
+) -> ShardChunk {
+    create_chunk_with_gas_used(prev, signer, transactions, 0)
+}
+
+fn create_chunk_with_gas_used(
+    prev: &Block,
+    signer: &dyn ValidatorSigner,
+    transactions: Vec<SignedTransaction>,
+    gas_used: Gas,
+) -> ShardChunk {
+    let (tx_root, _) = merklize(&transactions);
+    let mut rs = ReedSolomonWrapper::new(1, 2);
+    let (encoded_chunk, _) = EncodedShardChunk::new(
+        *prev.hash(),
+        StateRoot::default(),
+        CryptoHash::default(),
+        prev.header().height() + 1,
+        0,
+        &mut rs,
+        gas_used,
+        1_000_000,
+        0,
+        tx_root,
+        vec![],
+        transactions,
+        &vec![],
+        CryptoHash::default(),
+        signer,
+        PROTOCOL_VERSION,
+    )
+    .unwrap();
+    let mut chunk = encoded_chunk.decode_chunk(1).unwrap();
+    chunk.set_height_included(prev.header().height() + 1);
+    chunk
+}
+
+#[test]
+fn tx_inclusion_proof() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let tx_signer = InMemorySigner::from_seed("test0", KeyType::ED25519, "test0");
+    let transactions: Vec<SignedTransaction> = (1..4)
+        .map(|nonce| {
+            SignedTransaction::send_money(
+                nonce,
+                "test0".to_string(),
+                "test1".to_string(),
+                &tx_signer,
+                100,
+                *genesis.hash(),
+            )
+        })
+        .collect();
+    let (tx_root, _) = merklize(&transactions);
+    let chunk = create_chunk(&genesis, &*signer, transactions.clone());
+    let mut block = Block::empty(&genesis, &*signer);
+    block.set_chunks(vec![chunk.cloned_header()]);
+    block.mut_header().get_mut().inner_rest.chunk_tx_root =
+        Block::compute_chunk_tx_root(block.chunks().iter());
+    block.mut_header().resign(&*signer);
+    let block_hash = *block.hash();
+    let chunk_tx_root = *block.header().chunk_tx_root();
+
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_block(block);
+    store_update.save_chunk(chunk);
+    store_update.commit().unwrap();
+
+    let tx = &transactions[1];
+    let proof = chain.get_tx_inclusion_proof(&block_hash, &tx.get_hash()).unwrap();
+    assert_eq!(proof.tx_root, tx_root);
+    assert!(proof.verify(tx, &chunk_tx_root));
+    assert!(!proof.verify(&transactions[0], &chunk_tx_root));
+
+    let absent_tx = SignedTransaction::send_money(
+        4,
+        "test0".to_string(),
+        "test1".to_string(),
+        &tx_signer,
+        100,
+        *genesis.hash(),
+    );
+    assert!(matches!(
+        chain.get_tx_inclusion_proof(&block_hash, &absent_tx.get_hash()).unwrap_err().kind(),
+        ErrorKind::DBNotFoundErr(_)
+    ));
+}
+
+#[test]
+fn tracked_shards() {
+    init_test_logger();
+    let (chain, _, _) = setup_with_validators(
+        vec!["test0".to_string(), "test1".to_string(), "test2".to_string(), "test3".to_string()],
+        2,
+        4,
+        10,
+        100,
+    );
+    assert_eq!(chain.tracked_shards(&Some("test1".to_string())), vec![0, 1]);
+    assert_eq!(chain.tracked_shards(&Some("test3".to_string())), vec![2, 3]);
+    assert!(chain.tracked_shards(&Some("other".to_string())).is_empty());
+    assert!(chain.tracked_shards(&None).is_empty());
+}
+
+#[test]
+fn max_reorg_depth() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.max_reorg_depth = Some(2);
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let mut main = vec![b1.clone()];
+    for i in 2..=5 {
+        main.push(Block::empty_with_height(main.last().unwrap(), i, &*signer));
+    }
+    // The fork stays below the head until its last block, so that no tie at the head's height
+    // is resolved by the hashes.
+    let mut fork = vec![Block::empty_with_height(&b1, 2, &*signer)];
+    for i in 3..=4 {
+        fork.push(Block::empty_with_height(fork.last().unwrap(), i, &*signer));
+    }
+    let fork_head = Block::empty_with_height(fork.last().unwrap(), 6, &*signer);
+    for block in main.iter().chain(fork.iter()) {
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+    }
+    // The fork at height 6 would revert 4 blocks, which is over the limit.
+    let fork_head_hash = *fork_head.hash();
+    let err = chain
+        .process_block(&None, fork_head, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ReorgTooDeep { depth: 4, limit: 2 });
+    assert!(!err.is_bad_data());
+    assert_eq!(chain.head().unwrap().last_block_hash, *main.last().unwrap().hash());
+    assert!(chain.get_block(&fork_head_hash).is_err());
+
+    // A reorg within the limit is accepted.
+    let c4 = Block::empty_with_height(&main[2], 4, &*signer);
+    let c7 = Block::empty_with_height(&c4, 7, &*signer);
+    let c7_hash = *c7.hash();
+    chain.process_block(&None, c4, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    chain.process_block(&None, c7, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, c7_hash);
+}
+
+#[test]
+fn chunk_tx_count() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let tx_signer = InMemorySigner::from_seed("test0", KeyType::ED25519, "test0");
+    let transactions = (1..=5)
+        .map(|nonce| {
+            SignedTransaction::send_money(
+                nonce,
+                "test0".to_string(),
+                "test1".to_string(),
+                &tx_signer,
+                100,
+                *genesis.hash(),
+            )
+        })
+        .collect();
+    let chunk = create_chunk(&genesis, &*signer, transactions);
+    let chunk_hash = chunk.chunk_hash();
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_chunk(chunk);
+    store_update.commit().unwrap();
+    assert_eq!(chain.get_chunk_tx_count(&chunk_hash).unwrap(), 5);
+}
+
+#[test]
+fn get_chunk_at() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let chunk = create_chunk(&genesis, &*signer, vec![]);
+    let chunk_hash = chunk.chunk_hash();
+    let mut b1 = Block::empty(&genesis, &*signer);
+    b1.set_chunks(vec![chunk.cloned_header()]);
+    b1.mut_header().resign(&*signer);
+    let b2 = Block::empty(&b1, &*signer);
+
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_chunk(chunk);
+    for block in vec![b1, b2] {
+        store_update.save_block_header(block.header().clone()).unwrap();
+        store_update.save_head(&Tip::from_header(block.header())).unwrap();
+        store_update.save_block(block);
+    }
+    store_update.commit().unwrap();
+
+    assert_eq!(chain.get_chunk_at(1, 0).unwrap().chunk_hash(), chunk_hash);
+    assert_eq!(chain.get_chunk_at(2, 0).unwrap_err().kind(), ErrorKind::ChunkMissing(chunk_hash));
+    assert_eq!(chain.get_chunk_at(1, 1).unwrap_err().kind(), ErrorKind::InvalidShardId(1));
+}
+
+#[test]
+fn verify_block_merkle() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut prev = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    for _ in 0..4 {
+        let block = Block::empty(&prev, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        prev = block;
+    }
+    assert_eq!(chain.verify_block_merkle(0, 4).unwrap(), Vec::<u64>::new());
+
+    let mut corrupted = chain.get_block_by_height(2).unwrap().clone();
+    corrupted.mut_header().get_mut().inner_lite.prev_state_root = CryptoHash::default();
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_block(corrupted);
+    store_update.commit().unwrap();
+    assert_eq!(chain.verify_block_merkle(0, 4).unwrap(), vec![2]);
+    assert_eq!(chain.verify_block_merkle(3, 10).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn process_blocks_batch() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let other_signer = InMemoryValidatorSigner::from_seed("other", KeyType::ED25519, "other");
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let bad_b3 = Block::empty(&b2, &other_signer);
+
+    let batch = vec![
+        (b1.clone(), Provenance::NONE),
+        (b2.clone(), Provenance::NONE),
+        (bad_b3, Provenance::NONE),
+    ];
+    let (index, _) = chain.process_blocks_batch(&None, batch, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(index, Some(2));
+    // Nothing from the failed batch is committed.
+    assert_eq!(chain.head().unwrap().height, 0);
+    assert!(!chain.block_exists(b1.hash()).unwrap());
+
+    let batch = vec![(b1, Provenance::NONE), (b2, Provenance::NONE), (b3, Provenance::NONE)];
+    let tip = chain.process_blocks_batch(&None, batch, |_| {}, |_| {}, |_| {}).unwrap().unwrap();
+    assert_eq!(tip.height, 3);
+    assert_eq!(chain.head().unwrap().height, 3);
+}
+
+#[test]
+fn subscribe_reorgs() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let reorgs = chain.subscribe_reorgs();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let c2 = Block::empty_with_height(&b1, 2, &*signer);
+    let c4 = Block::empty_with_height(&c2, 4, &*signer);
+    let c5 = Block::empty_with_height(&c4, 5, &*signer);
+    let (b1_hash, b2_hash, b3_hash) = (*b1.hash(), *b2.hash(), *b3.hash());
+    let (c2_hash, c4_hash, c5_hash) = (*c2.hash(), *c4.hash(), *c5.hash());
+    for block in vec![b1, b2, b3, c2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert!(reorgs.try_recv().is_err());
+
+    chain.process_block(&None, c4, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    let event = reorgs.try_recv().unwrap();
+    assert_eq!(event.common_ancestor, b1_hash);
+    assert_eq!(event.reverted, vec![b3_hash, b2_hash]);
+    assert_eq!(event.applied, vec![c2_hash, c4_hash]);
+
+    // Extending the new head is not a reorg.
+    chain.process_block(&None, c5, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert!(reorgs.try_recv().is_err());
+    assert_eq!(chain.head().unwrap().last_block_hash, c5_hash);
+}
+
+#[test]
+fn path_from_head_to() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let c2 = Block::empty_with_height(&b1, 2, &*signer);
+    let unknown = Block::empty(&c2, &*signer);
+    let (b1_hash, b2_hash, b3_hash, c2_hash) = (*b1.hash(), *b2.hash(), *b3.hash(), *c2.hash());
+    for block in vec![b1, b2, b3, c2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert_eq!(chain.head().unwrap().last_block_hash, b3_hash);
+
+    assert_eq!(chain.path_from_head_to(&b3_hash).unwrap(), vec![b3_hash]);
+    assert_eq!(chain.path_from_head_to(&b2_hash).unwrap(), vec![b2_hash]);
+    assert_eq!(chain.path_from_head_to(&c2_hash).unwrap(), vec![b1_hash, c2_hash]);
+    assert!(chain.path_from_head_to(unknown.hash()).is_err());
+}
+
+#[test]
+fn accepted_block_reorg() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let c3 = Block::empty_with_height(&b1, 3, &*signer);
+    let (b1_hash, b2_hash, c3_hash) = (*b1.hash(), *b2.hash(), *c3.hash());
+    let accepted = RefCell::new(vec![]);
+    let block_accepted = |block: AcceptedBlock| accepted.borrow_mut().push(block);
+    for block in vec![b1, b2, c3] {
+        chain
+            .process_block(&None, block, Provenance::PRODUCED, block_accepted, |_| {}, |_| {})
+            .unwrap();
+    }
+    let accepted = accepted.into_inner();
+    assert_eq!(accepted.len(), 3);
+    assert!(accepted[..2].iter().all(|block| block.reorg.is_none()));
+    assert_eq!(accepted[2].status, BlockStatus::Reorg(b2_hash));
+    let reorg = accepted[2].reorg.clone().unwrap();
+    assert_eq!(reorg.common_ancestor, b1_hash);
+    assert_eq!(reorg.reverted, vec![b2_hash]);
+    assert_eq!(reorg.applied, vec![c3_hash]);
+}
+
+#[derive(Default)]
+struct RecordingHandler {
+    accepted: Arc<Mutex<Vec<CryptoHash>>>,
+    reorgs: Arc<Mutex<Vec<ReorgEvent>>>,
+}
+
+impl ChainEventHandler for RecordingHandler {
+    fn on_block_accepted(&mut self, block: &AcceptedBlock) {
+        self.accepted.lock().unwrap().push(block.hash);
+    }
+
+    fn on_reorg(&mut self, event: &ReorgEvent) {
+        self.reorgs.lock().unwrap().push(event.clone());
+    }
+}
+
+#[test]
+fn event_handlers() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let handler = RecordingHandler::default();
+    let (accepted, reorgs) = (handler.accepted.clone(), handler.reorgs.clone());
+    chain.register_handler(Box::new(handler));
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let c3 = Block::empty_with_height(&b1, 3, &*signer);
+    let (b1_hash, b2_hash, c3_hash) = (*b1.hash(), *b2.hash(), *c3.hash());
+    let callback_accepted = RefCell::new(vec![]);
+    for block in vec![b1, b2, c3] {
+        chain
+            .process_block(
+                &None,
+                block,
+                Provenance::PRODUCED,
+                |block: AcceptedBlock| callback_accepted.borrow_mut().push(block.hash),
+                |_| {},
+                |_| {},
+            )
+            .unwrap();
+    }
+    // Handlers are notified in addition to the callbacks.
+    assert_eq!(*accepted.lock().unwrap(), vec![b1_hash, b2_hash, c3_hash]);
+    assert_eq!(callback_accepted.into_inner(), vec![b1_hash, b2_hash, c3_hash]);
+    let reorgs = reorgs.lock().unwrap();
+    assert_eq!(reorgs.len(), 1);
+    assert_eq!(reorgs[0].common_ancestor, b1_hash);
+    assert_eq!(reorgs[0].reverted, vec![b2_hash]);
+    assert_eq!(reorgs[0].applied, vec![c3_hash]);
+}
+
+#[test]
+fn process_block_collect() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let (b1_hash, b2_hash) = (*b1.hash(), *b2.hash());
+
+    let err = chain.process_block_collect(&None, b2, Provenance::NONE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Orphan);
+
+    // Accepting the parent also accepts the orphan, each recorded as an event.
+    let (new_head, events) = chain.process_block_collect(&None, b1, Provenance::NONE).unwrap();
+    assert_eq!(new_head.unwrap().last_block_hash, b2_hash);
+    let accepted = events
+        .into_iter()
+        .map(|event| match event {
+            ChainEvent::BlockAccepted(block) => block.hash,
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(accepted, vec![b1_hash, b2_hash]);
+}
+
+#[test]
+fn reject_outdated_protocol_version() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut block = Block::empty(&genesis, &*signer);
+    block.mut_header().get_mut().inner_rest.latest_protocol_version = PROTOCOL_VERSION - 1;
+    block.mut_header().resign(&*signer);
+    let block_hash = *block.hash();
+    let err = chain
+        .process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unfit("protocol version mismatch".to_string()));
+    assert!(chain.get_block(&block_hash).is_err());
+    assert_eq!(chain.head().unwrap().height, 0);
+}
+
+#[test]
+fn first_block_on_or_after() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let genesis_timestamp = genesis.header().raw_timestamp();
+    let second = 1_000_000_000;
+    let mut prev = genesis.clone();
+    let mut blocks = vec![];
+    for i in 1..=5 {
+        // Skip every other height to have gaps on the canonical chain.
+        let mut block = Block::empty_with_height(&prev, i * 2, &*signer);
+        block.mut_header().get_mut().inner_lite.timestamp = genesis_timestamp + i * second;
+        block.mut_header().resign(&*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block.clone());
+        prev = block;
+    }
+
+    let header = chain.first_block_on_or_after(genesis.header().timestamp()).unwrap();
+    assert_eq!(header.hash(), genesis.hash());
+    let header = chain.first_block_on_or_after(blocks[1].header().timestamp()).unwrap();
+    assert_eq!(header.hash(), blocks[1].hash());
+    let header =
+        chain.first_block_on_or_after(from_timestamp(genesis_timestamp + 5 * second / 2)).unwrap();
+    assert_eq!(header.hash(), blocks[2].hash());
+    assert!(chain.first_block_on_or_after(from_timestamp(genesis_timestamp + 6 * second)).is_err());
+}
+
+#[test]
+fn reject_duplicate_chunks() {
+    init_test_logger();
+    let (mut chain, _, signers) =
+        setup_with_validators(vec!["test0".to_string(), "test1".to_string()], 1, 2, 10, 100);
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut block = Block::empty(&genesis, &*signers[0]);
+    let chunk_header = block.chunks()[0].clone();
+    block.set_chunks(vec![chunk_header.clone(), chunk_header]);
+    let block_hash = *block.hash();
+    let err = chain
+        .process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidChunk);
+    assert!(chain.get_block(&block_hash).is_err());
+}
+
+/// Replaces the chunks of the block, updating the header fields derived from them.
+fn set_chunks_and_resign(
+    block: &mut Block,
+    chunks: Vec<ShardChunkHeader>,
+    signer: &dyn ValidatorSigner,
+) {
+    block.set_chunks(chunks);
+    let height = block.header().height();
+    let chunk_mask =
+        block.chunks().iter().map(|chunk| chunk.height_included() == height).collect::<Vec<_>>();
+    let prev_state_root = Block::compute_state_root(block.chunks().iter());
+    let chunk_receipts_root = Block::compute_chunk_receipts_root(block.chunks().iter());
+    let chunk_headers_root = Block::compute_chunk_headers_root(block.chunks().iter()).0;
+    let chunk_tx_root = Block::compute_chunk_tx_root(block.chunks().iter());
+    let header = block.mut_header().get_mut();
+    header.inner_lite.prev_state_root = prev_state_root;
+    header.inner_rest.chunk_receipts_root = chunk_receipts_root;
+    header.inner_rest.chunk_headers_root = chunk_headers_root;
+    header.inner_rest.chunk_tx_root = chunk_tx_root;
+    header.inner_rest.chunks_included =
+        chunk_mask.iter().filter(|included| **included).count() as u64;
+    header.inner_rest.chunk_mask = chunk_mask;
+    block.mut_header().resign(signer);
+}
+
+#[test]
+fn reject_chunk_included_again() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let chunk = create_chunk(&genesis, &*signer, vec![]);
+    let mut b1 = Block::empty(&genesis, &*signer);
+    set_chunks_and_resign(&mut b1, vec![chunk.cloned_header()], &*signer);
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_chunk(chunk.clone());
+    store_update.commit().unwrap();
+    chain.process_block(&None, b1.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+
+    // The chunk applied at height 1 shows up as a new chunk again at height 2.
+    let mut chunk_header = chunk.cloned_header();
+    *chunk_header.height_included_mut() = 2;
+    let mut b2 = Block::empty(&b1, &*signer);
+    set_chunks_and_resign(&mut b2, vec![chunk_header], &*signer);
+    assert_eq!(b2.header().chunk_mask(), &[true]);
+    let err = chain
+        .process_block(&None, b2.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidChunk);
+    assert!(chain.get_block(b2.hash()).is_err());
+}
+
+#[test]
+fn final_head_advances_and_does_not_regress() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut blocks = vec![genesis];
+    for _ in 1..=5 {
+        let block = Block::empty(blocks.last().unwrap(), &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+        // Block is final once two consecutive blocks are built on top of it.
+        let expected_final = &blocks[blocks.len().saturating_sub(3)];
+        assert_eq!(chain.final_head().unwrap().last_block_hash, *expected_final.hash());
+    }
+    assert_eq!(chain.final_head().unwrap().height, 3);
+
+    // Shallow reorg to a block that skips a height doesn't move the final head back.
+    let fork = Block::empty_with_height(&blocks[4], 6, &*signer);
+    let fork_hash = *fork.hash();
+    chain.process_block(&None, fork, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, fork_hash);
+    assert_eq!(chain.final_head().unwrap().last_block_hash, *blocks[3].hash());
+}
+
+#[test]
+fn reject_chunk_from_wrong_producer() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let forger = InMemoryValidatorSigner::from_seed("forger", KeyType::ED25519, "forger");
+    let chunk = create_chunk(&genesis, &forger, vec![]);
+    let block = block_with_new_chunk(&genesis, &chunk, &*signer);
+    let block_hash = *block.hash();
+    let err =
+        chain.process_block(&None, block, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidChunk);
+    assert!(chain.get_block(&block_hash).is_err());
+}
+
+#[test]
+fn reject_chunk_on_unknown_block() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    // Not the genesis block and never processed, so the chunk builds on an unknown block.
+    let unknown = Block::empty_with_height(&genesis, 0, &*signer);
+    let chunk = create_chunk(&unknown, &*signer, vec![]);
+    let block = block_with_new_chunk(&genesis, &chunk, &*signer);
+    let block_hash = *block.hash();
+    let err =
+        chain.process_block(&None, block, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidChunk);
+    assert!(chain.get_block(&block_hash).is_err());
+}
+
+/// Builds an empty block on top of `prev` that includes `chunk` as a new chunk.
+fn block_with_new_chunk(prev: &Block, chunk: &ShardChunk, signer: &dyn ValidatorSigner) -> Block {
+    let mut block = Block::empty(prev, signer);
+    let chunks = vec![chunk.cloned_header()];
+    block.set_chunks(chunks.clone());
+    let header = block.mut_header().get_mut();
+    header.inner_lite.prev_state_root = Block::compute_state_root(chunks.iter());
+    header.inner_rest.chunk_receipts_root = Block::compute_chunk_receipts_root(chunks.iter());
+    header.inner_rest.chunk_headers_root = Block::compute_chunk_headers_root(chunks.iter()).0;
+    header.inner_rest.chunk_tx_root = Block::compute_chunk_tx_root(chunks.iter());
+    header.inner_rest.chunk_mask = vec![true];
+    block.mut_header().resign(signer);
+    block
+}
+
+#[test]
+fn stage_block_commits_with_external_changes() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let block = Block::empty(&genesis, &*signer);
+    let block_hash = *block.hash();
+
+    // Dropping a staged block leaves the store untouched.
+    let staged = chain.stage_block(&None, &block, &Provenance::PRODUCED).unwrap();
+    drop(staged);
+    assert!(chain.get_block(&block_hash).is_err());
+
+    let mut external_update = chain.store().store().store_update();
+    external_update.set(DBCol::ColBlockMisc, b"external_index", block_hash.as_ref());
+    let mut staged = chain.stage_block(&None, &block, &Provenance::PRODUCED).unwrap();
+    assert_eq!(staged.status, BlockStatus::Next);
+    assert_eq!(staged.head.as_ref().unwrap().last_block_hash, block_hash);
+    staged.merge(external_update);
+    staged.commit().unwrap();
+
+    assert_eq!(chain.head().unwrap().last_block_hash, block_hash);
+    assert!(chain.get_block(&block_hash).is_ok());
+    assert_eq!(
+        chain.store().store().get(DBCol::ColBlockMisc, b"external_index").unwrap(),
+        Some(block_hash.as_ref().to_vec())
+    );
+}
+
+#[test]
+fn get_block_height() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let c3 = Block::empty_with_height(&b1, 3, &*signer);
+    for block in vec![b1.clone(), c3.clone()] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert_eq!(chain.get_block_height(genesis.hash()).unwrap(), genesis.header().height());
+    assert_eq!(chain.get_block_height(b1.hash()).unwrap(), 1);
+    assert_eq!(chain.get_block_height(c3.hash()).unwrap(), 3);
+    assert!(chain.get_block_height(&hash(&[1])).is_err());
+}
+
+#[test]
+fn get_next_block_hash() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b4 = Block::empty_with_height(&b2, 4, &*signer);
+    let c2 = Block::empty_with_height(&b1, 2, &*signer);
+    let (b1_hash, b2_hash, b4_hash, c2_hash) = (*b1.hash(), *b2.hash(), *b4.hash(), *c2.hash());
+    for block in vec![b1, b2, b4, c2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert_eq!(chain.head().unwrap().last_block_hash, b4_hash);
+
+    assert_eq!(chain.get_next_block_hash(genesis.hash()).unwrap(), b1_hash);
+    assert_eq!(chain.get_next_block_hash(&b1_hash).unwrap(), b2_hash);
+    // Skipped heights are passed over.
+    assert_eq!(chain.get_next_block_hash(&b2_hash).unwrap(), b4_hash);
+    for hash in vec![b4_hash, c2_hash] {
+        assert!(matches!(
+            chain.get_next_block_hash(&hash).unwrap_err().kind(),
+            ErrorKind::DBNotFoundErr(_)
+        ));
+    }
+}
+
+#[test]
+fn is_epoch_start_block() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty_with_epoch(
+        &b1,
+        2,
+        EpochId(*b1.hash()),
+        b1.header().next_epoch_id().clone(),
+        *b1.header().next_bp_hash(),
+        &*signer,
+        &mut PartialMerkleTree::default(),
+    );
+    let b3 = Block::empty(&b2, &*signer);
+    let b4 = Block::empty(&b3, &*signer);
+
+    let mut store_update = chain.mut_store().store_update();
+    for block in vec![&b1, &b2, &b3, &b4] {
+        store_update.save_block_header(block.header().clone()).unwrap();
+    }
+    store_update.commit().unwrap();
+
+    assert!(chain.is_epoch_start_block(genesis.hash()).unwrap());
+    assert!(!chain.is_epoch_start_block(b1.hash()).unwrap());
+    assert!(chain.is_epoch_start_block(b2.hash()).unwrap());
+    assert!(!chain.is_epoch_start_block(b3.hash()).unwrap());
+    assert!(!chain.is_epoch_start_block(b4.hash()).unwrap());
+
+    assert_eq!(chain.get_epoch_id(b1.hash()).unwrap(), *b1.header().epoch_id());
+    assert_eq!(chain.get_epoch_id(b4.hash()).unwrap(), EpochId(*b1.hash()));
+    assert!(chain.get_epoch_id(&hash(&[1])).is_err());
+}
+
+#[test]
+fn keep_orphans_of_tampered_block() {
+    init_test_logger();
+    let (mut chain, _, signers) =
+        setup_with_validators(vec!["test0".to_string(), "test1".to_string()], 1, 2, 10, 100);
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    // Block producer for height `h` is `signers[h % 2]`.
+    let mut invalid_block = Block::empty(&genesis, &*signers[1]);
+    let chunk_header = invalid_block.chunks()[0].clone();
+    invalid_block.set_chunks(vec![chunk_header.clone(), chunk_header]);
+    let child = Block::empty(&invalid_block, &*signers[0]);
+    let grandchild = Block::empty(&child, &*signers[1]);
+
+    let err = chain
+        .process_block(&None, invalid_block, Provenance::NONE, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidChunk);
+    // The chunks aren't covered by the block hash, so the honest block may still arrive.
+    for block in vec![child, grandchild] {
+        let block_hash = *block.hash();
+        let err = chain
+            .process_block(&None, block, Provenance::NONE, |_| {}, |_| {}, |_| {})
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Orphan);
+        assert!(chain.is_orphan(&block_hash));
+    }
+    assert_eq!(chain.orphans_len(), 2);
+}
+
+#[test]
+fn orphans_survive_restart() {
+    init_test_logger();
+    let runtime = Arc::new(KeyValueRuntime::new(create_test_store()));
+    let chain_genesis = ChainGenesis::test();
+    let mut chain =
+        Chain::new(runtime.clone(), &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap();
+    let signer = InMemoryValidatorSigner::from_seed("test", KeyType::ED25519, "test");
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    for i in 0..5 {
+        blocks.push(Block::empty(&blocks[i], &signer));
+    }
+    for block in blocks[1..3].iter() {
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+    }
+    // Block 5 stays an orphan since block 4 is never seen.
+    let res =
+        chain.process_block(&None, blocks[5].clone(), Provenance::NONE, |_| {}, |_| {}, |_| {});
+    assert_eq!(res.unwrap_err().kind(), ErrorKind::Orphan);
+    // Block 3 became an orphan before the restart, but its parent is known by now.
+    let mut store_update = chain.mut_store().store_update();
+    store_update.add_orphan(blocks[3].clone(), Provenance::SYNC);
+    store_update.add_orphan(blocks[5].clone(), Provenance::NONE);
+    store_update.commit().unwrap();
+    drop(chain);
+
+    let mut chain =
+        Chain::new(runtime.clone(), &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap();
+    assert_eq!(chain.orphans_len(), 0);
+    chain.restore_orphans().unwrap();
+    assert_eq!(chain.orphans_len(), 2);
+    assert!(chain.is_orphan(blocks[3].hash()));
+    assert!(chain.is_orphan(blocks[5].hash()));
+    let accepted = RefCell::new(vec![]);
+    let tip = chain
+        .check_restored_orphans(&None, |block| accepted.borrow_mut().push(block), |_| {}, |_| {})
+        .unwrap();
+    assert_eq!(tip.unwrap().height, 3);
+    assert_eq!(chain.orphans_len(), 1);
+    let accepted = accepted.into_inner();
+    assert_eq!(accepted.len(), 1);
+    assert_eq!(accepted[0].provenance, Provenance::SYNC);
+
+    chain.flush_orphans().unwrap();
+    drop(chain);
+    let mut chain =
+        Chain::new(runtime.clone(), &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap();
+    chain.restore_orphans().unwrap();
+    assert_eq!(chain.orphans_len(), 1);
+    assert!(chain.is_orphan(blocks[5].hash()));
+
+    // Restored orphans are removed from the store, and so are orphans older than the pool allows.
+    chain.flush_orphans().unwrap();
+    drop(chain);
+    let mut chain =
+        Chain::new(runtime, &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap();
+    chain.set_orphan_pool_config(OrphanPoolConfig {
+        max_age_secs: 0,
+        ..OrphanPoolConfig::default()
+    });
+    chain.restore_orphans().unwrap();
+    assert_eq!(chain.orphans_len(), 0);
+    assert!(chain.store().iterate_orphans().unwrap().is_empty());
+}
+
+#[test]
+fn missing_state_parts() {
+    init_test_logger();
+    let (chain, _, _) = setup();
+    let sync_hash = *chain.genesis().hash();
+    let mut store_update = chain.store().store().store_update();
+    for part_id in vec![0, 2, 3] {
+        let key = StatePartKey(sync_hash, 0, part_id).try_to_vec().unwrap();
+        store_update.set(DBCol::ColStateParts, &key, &[part_id as u8]);
+    }
+    // Parts of other shards don't count.
+    let key = StatePartKey(sync_hash, 1, 1).try_to_vec().unwrap();
+    store_update.set(DBCol::ColStateParts, &key, &[1]);
+    store_update.commit().unwrap();
+
+    assert_eq!(chain.missing_state_parts(0, &sync_hash, 5), vec![1, 4]);
+    assert_eq!(chain.missing_state_parts(1, &sync_hash, 3), vec![0, 2]);
+    assert_eq!(chain.missing_state_parts(0, &sync_hash, 0), Vec::<u64>::new());
+}
+
+#[test]
+fn freeze_head() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let c4 = Block::empty_with_height(&b1, 4, &*signer);
+    let (b1_hash, b2_hash) = (*b1.hash(), *b2.hash());
+    for block in vec![b1, b2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    let guard = chain.freeze_head().unwrap();
+    assert_eq!(guard.head().last_block_hash, b2_hash);
+
+    chain.process_block(&None, b3, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().height, 3);
+    assert!(chain.get_frozen_header_by_height(&guard, 3).is_err());
+    assert_eq!(chain.get_frozen_block_by_height(&guard, 2).unwrap().hash(), &b2_hash);
+
+    // After a reorg the frozen head is no longer canonical, but still resolves its own chain.
+    chain.process_block(&None, c4, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert!(chain.get_header_by_height(2).is_err());
+    assert_eq!(chain.get_frozen_header_by_height(&guard, 2).unwrap().hash(), &b2_hash);
+    assert_eq!(chain.get_frozen_header_by_height(&guard, 1).unwrap().hash(), &b1_hash);
+}
+
+#[test]
+fn block_producer_schedule() {
+    init_test_logger();
+    let (mut chain, _, signers) = setup_with_validators(
+        vec!["test0".to_string(), "test1".to_string(), "test2".to_string()],
+        1,
+        1,
+        10,
+        100,
+    );
+    let mut prev = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut producers = vec![];
+    for height in 1..=4 {
+        let signer = &signers[(height % 3) as usize];
+        let block = Block::empty(&prev, &**signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        producers.push((height, signer.validator_id().clone()));
+        prev = block;
+    }
+    let epoch_id = chain.head().unwrap().epoch_id;
+    let schedule = chain.block_producer_schedule(&epoch_id).unwrap();
+    assert_eq!(schedule.len(), 10);
+    assert!(schedule.windows(2).all(|w| w[1].0 == w[0].0 + 1));
+    // Producers of the blocks in the epoch follow the schedule.
+    for producer in producers {
+        assert!(schedule.contains(&producer));
+    }
+    assert!(chain.block_producer_schedule(&EpochId(hash(&[1]))).is_err());
+}
+
+#[test]
+fn detect_equivocation() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let mut c2 = Block::empty(&b1, &*signer);
+    c2.mut_header().get_mut().inner_lite.timestamp += 1;
+    c2.mut_header().resign(&*signer);
+    let (b2_hash, c2_hash) = (*b2.hash(), *c2.hash());
+    assert_ne!(b2_hash, c2_hash);
+    let challenges = RefCell::new(vec![]);
+    for block in vec![b1, b2, c2] {
+        chain
+            .process_block(
+                &None,
+                block,
+                Provenance::PRODUCED,
+                |_| {},
+                |_| {},
+                |challenge| challenges.borrow_mut().push(challenge),
+            )
+            .unwrap();
+    }
+    assert_eq!(challenges.borrow().len(), 1);
+    assert_eq!(chain.detect_equivocation(1).unwrap(), None);
+    assert_eq!(chain.detect_equivocation(5).unwrap(), None);
+    let proof = chain.detect_equivocation(2).unwrap().unwrap();
+    assert_eq!(&proof.producer, signer.validator_id());
+    let mut hashes = vec![*proof.left_block_header.hash(), *proof.right_block_header.hash()];
+    hashes.sort();
+    let mut expected = vec![b2_hash, c2_hash];
+    expected.sort();
+    assert_eq!(hashes, expected);
+}
+
+#[test]
+fn take_equivocation_evidence() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let mut c2 = Block::empty(&b1, &*signer);
+    c2.mut_header().get_mut().inner_lite.timestamp += 1;
+    c2.mut_header().resign(&*signer);
+    let (b2_hash, c2_hash) = (*b2.hash(), *c2.hash());
+    for block in vec![b1, b2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert!(chain.take_equivocation_evidence().is_empty());
+
+    // The second block is still stored, and the evidence names the one seen first.
+    let challenges = RefCell::new(vec![]);
+    chain
+        .process_block(
+            &None,
+            c2,
+            Provenance::PRODUCED,
+            |_| {},
+            |_| {},
+            |challenge| challenges.borrow_mut().push(challenge),
+        )
+        .unwrap();
+    assert!(chain.get_block(&c2_hash).is_ok());
+    let evidence = chain.take_equivocation_evidence();
+    assert_eq!(evidence.len(), 1);
+    assert_eq!(&evidence[0].producer, signer.validator_id());
+    assert_eq!(evidence[0].left_block_header.hash(), &b2_hash);
+    assert_eq!(evidence[0].right_block_header.hash(), &c2_hash);
+    assert!(chain.take_equivocation_evidence().is_empty());
+    // Header validation challenges the same pair.
+    assert_eq!(
+        challenges.into_inner(),
+        vec![ChallengeBody::BlockDoubleSign(evidence[0].clone().into())]
+    );
+}
+
+#[test]
+fn equal_height_tie_break() {
+    init_test_logger();
+    for reverse in vec![false, true] {
+        let (mut chain, _, signer) = setup();
+        let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+        let b1 = Block::empty(&genesis, &*signer);
+        let b2 = Block::empty(&b1, &*signer);
+        let mut c2 = Block::empty(&b1, &*signer);
+        c2.mut_header().get_mut().inner_lite.timestamp += 1;
+        c2.mut_header().resign(&*signer);
+        let mut forks = vec![b2, c2];
+        if reverse {
+            forks.reverse();
+        }
+        let expected = if checked_feature!(
+            "protocol_feature_fork_choice_tie_break",
+            ForkChoiceTieBreak,
+            PROTOCOL_VERSION
+        ) {
+            std::cmp::min(*forks[0].hash(), *forks[1].hash())
+        } else {
+            *forks[0].hash()
+        };
+
+        // Header sync picks the smaller hash, or the first one seen without the tie break.
+        chain.sync_block_headers(vec![b1.header().clone()], |_| {}).unwrap();
+        for block in forks.iter() {
+            chain.sync_block_headers(vec![block.header().clone()], |_| {}).unwrap();
+        }
+        assert_eq!(chain.header_head().unwrap().last_block_hash, expected);
+
+        // And so does block processing.
+        for block in vec![b1].into_iter().chain(forks) {
+            chain
+                .process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+                .unwrap();
+        }
+        assert_eq!(chain.head().unwrap().last_block_hash, expected);
+        assert_eq!(chain.header_head().unwrap().last_block_hash, expected);
+        assert_eq!(chain.get_header_by_height(2).unwrap().hash(), &expected);
+    }
+}
+
+#[test]
+fn forks_at_head() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    assert_eq!(chain.forks_at_head().unwrap(), vec![chain.head().unwrap()]);
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let mut c2 = Block::empty(&b1, &*signer);
+    c2.mut_header().get_mut().inner_lite.timestamp += 1;
+    c2.mut_header().resign(&*signer);
+    let c2_tip = Tip::from_header(c2.header());
+    for block in vec![b1, b2, c2] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    let head = chain.head().unwrap();
+    let forks = chain.forks_at_head().unwrap();
+    assert_eq!(forks.len(), 2);
+    assert_eq!(forks[0], head);
+    assert!(forks.contains(&c2_tip));
+}
+
+#[test]
+fn find_common_header_bounds_lookups() {
+    init_test_logger();
+    let (mut chain, _, _) = setup();
+    let genesis_hash = *chain.genesis().hash();
+    let unknown =
+        (0..MAX_COMMON_HEADER_LOOKUPS * 100).map(|i| hash(&i.to_le_bytes())).collect::<Vec<_>>();
+
+    let mut hashes = unknown[..MAX_COMMON_HEADER_LOOKUPS - 1].to_vec();
+    hashes.push(genesis_hash);
+    assert_eq!(chain.find_common_header(&hashes).unwrap().hash(), &genesis_hash);
+
+    let mut hashes = unknown.clone();
+    hashes.push(genesis_hash);
+    assert!(chain.find_common_header(&hashes).is_none());
+}
+
+#[test]
+fn has_shard_state() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let block = Block::empty(&genesis, &*signer);
+    assert!(chain.has_shard_state(0, genesis.hash()));
+    assert!(!chain.has_shard_state(1, genesis.hash()));
+    assert!(!chain.has_shard_state(0, block.hash()));
+    let me = Some(signer.validator_id().clone());
+    chain.process_block(&me, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert!(chain.has_shard_state(0, block.hash()));
+    assert!(chain.heights_missing_state(0).unwrap().is_empty());
+
+    // Without tracking any shard the chunk of the next block is not applied.
+    let next = Block::empty(&block, &*signer);
+    chain.process_block(&None, next.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert!(!chain.has_shard_state(0, next.hash()));
+    assert_eq!(chain.heights_missing_state(0).unwrap(), vec![next.header().height()]);
+}
+
+#[test]
+fn verify_genesis() {
+    init_test_logger();
+    let (mut chain, _, _) = setup();
+    chain.verify_genesis().unwrap();
+
+    let genesis_hash = *chain.genesis().hash();
+    let mut chunk_extra = chain.get_chunk_extra(&genesis_hash, 0).unwrap().clone();
+    *chunk_extra.state_root_mut() = hash(&[1]);
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_chunk_extra(&genesis_hash, 0, chunk_extra);
+    store_update.commit().unwrap();
+    match chain.verify_genesis().unwrap_err().kind() {
+        ErrorKind::Other(message) => assert!(message.contains("state root mismatch for shard 0")),
+        kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn metrics_snapshot() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let c3 = Block::empty_with_height(&b1, 3, &*signer);
+    let orphan = Block::empty(&Block::empty(&c3, &*signer), &*signer);
+    let mut past = Block::empty(&genesis, &*signer);
+    past.mut_header().get_mut().inner_lite.timestamp = genesis.header().raw_timestamp() - 1;
+    past.mut_header().resign(&*signer);
+    chain.process_block(&None, past, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    for block in vec![b1, b2, c3] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    chain.process_block(&None, orphan, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+
+    let metrics = chain.metrics_snapshot();
+    assert_eq!(metrics.blocks_accepted, 3);
+    assert_eq!(metrics.reorgs, 1);
+    assert_eq!(metrics.orphans_added, 1);
+    assert_eq!(metrics.blocks_missing_chunks, 0);
+    let past_time = ErrorKind::InvalidBlockPastTime(Utc::now(), Utc::now());
+    assert_eq!(metrics.invalid_blocks(&past_time), 1);
+    assert_eq!(metrics.invalid_blocks(&ErrorKind::InvalidChunkMask), 0);
+    assert_eq!(metrics.invalid_blocks_total(), 1);
+}
+
+#[test]
+fn apply_time_per_shard() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let me = Some(signer.validator_id().clone());
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    chain.process_block(&me, b1, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert!(chain.metrics_snapshot().apply_time_per_shard.is_empty());
+
+    chain.apply_time_warn_threshold = Some(std::time::Duration::from_secs(3600));
+    chain.process_block(&me, b2, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    let apply_time_per_shard = chain.metrics_snapshot().apply_time_per_shard;
+    assert_eq!(apply_time_per_shard.keys().collect::<Vec<_>>(), vec![&0]);
+}
+
+#[test]
+fn process_block_after_header() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let other_signer = InMemoryValidatorSigner::from_seed("other", KeyType::ED25519, "other");
+    let mut forged = b1.clone();
+    forged.mut_header().resign(&other_signer);
+    assert_eq!(forged.hash(), b1.hash());
+
+    // Skipping checks for a validated header must not let a different signature through.
+    chain.process_block_header(b1.header(), |_| panic!("Unexpected")).unwrap();
+    let err =
+        chain.process_block(&None, forged, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidSignature);
+
+    chain.process_block_header(b1.header(), |_| panic!("Unexpected")).unwrap();
+    chain.process_block(&None, b1.clone(), Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, *b1.hash());
+    assert_eq!(chain.get_block_header(b1.hash()).unwrap().signature(), b1.header().signature());
+}
+
+#[test]
+fn forged_signature_does_not_invalidate_block() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let other_signer = InMemoryValidatorSigner::from_seed("other", KeyType::ED25519, "other");
+    let mut forged = b1.clone();
+    forged.mut_header().resign(&other_signer);
+    assert_eq!(forged.hash(), b1.hash());
+
+    let err =
+        chain.process_block(&None, forged, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidSignature);
+    for block in vec![b1, b2, b3.clone()] {
+        chain.process_block(&None, block, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    assert_eq!(chain.head().unwrap().last_block_hash, *b3.hash());
+}
+
+#[test]
+fn rewind_to() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    for i in 1..=5 {
+        let block = Block::empty(&blocks[i - 1], &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    let fork = Block::empty(&blocks[3], &*signer);
+    chain.process_block(&None, fork.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    let header_head = chain.header_head().unwrap();
+    let final_height = chain.final_head().unwrap().height;
+    assert_eq!(final_height, 3);
+
+    assert!(chain.rewind_to(fork.hash()).is_err());
+    assert!(chain.rewind_to(blocks[2].hash()).is_err());
+    assert_eq!(chain.head().unwrap().last_block_hash, *blocks[5].hash());
+
+    chain.rewind_to(blocks[3].hash()).unwrap();
+    assert_eq!(chain.head().unwrap(), Tip::from_header(blocks[3].header()));
+    assert_eq!(chain.header_head().unwrap(), header_head);
+    assert_eq!(chain.mut_store().get_block_hash_by_height(3).unwrap(), *blocks[3].hash());
+    assert!(chain.mut_store().get_block_hash_by_height(4).is_err());
+    assert!(chain.mut_store().get_block_hash_by_height(5).is_err());
+    // Blocks above the new head are still known.
+    assert!(chain.get_block(blocks[5].hash()).is_ok());
+}
+
+#[test]
+fn rewind_to_respects_finality_depth() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.finality_depth = Some(2);
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    // Skipped heights keep doomslug finality at genesis.
+    for height in vec![2, 4, 6, 8] {
+        let block = Block::empty_with_height(blocks.last().unwrap(), height, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    assert_eq!(chain.final_head().unwrap().height, 0);
+    assert_eq!(chain.finalized_head().unwrap().height, 6);
+
+    assert!(chain.rewind_to(blocks[2].hash()).is_err());
+    assert_eq!(chain.head().unwrap().last_block_hash, *blocks[4].hash());
+    chain.rewind_to(blocks[3].hash()).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, *blocks[3].hash());
+}
+
+#[test]
+fn get_blocks_by_height_range() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut prev = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    for height in vec![1, 2, 4, 5] {
+        let block = Block::empty_with_height(&prev, height, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        prev = block;
+    }
+    // Fork block at a height that is skipped on the canonical chain.
+    let b2 = chain.get_block_by_height(2).unwrap().clone();
+    let fork = Block::empty_with_height(&b2, 3, &*signer);
+    chain.process_block(&None, fork, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+
+    let heights =
+        |blocks: Vec<Block>| blocks.iter().map(|b| b.header().height()).collect::<Vec<_>>();
+    assert_eq!(heights(chain.get_blocks_by_height_range(2, 5).unwrap()), vec![2, 4, 5]);
+    assert_eq!(heights(chain.get_blocks_by_height_range(0, 1).unwrap()), vec![0, 1]);
+    assert_eq!(heights(chain.get_blocks_by_height_range(5, 10).unwrap()), vec![5]);
+    assert!(chain.get_blocks_by_height_range(3, 3).unwrap().is_empty());
+}
+
+#[test]
+fn ancestors() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty_with_height(&b2, 5, &*signer);
+    let expected = vec![*b3.hash(), *b2.hash(), *b1.hash(), *genesis.hash()];
+    for block in vec![b1, b2, b3] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    let hashes =
+        chain.ancestors(&expected[0]).map(|header| *header.unwrap().hash()).collect::<Vec<_>>();
+    assert_eq!(hashes, expected);
+    assert_eq!(chain.ancestors(genesis.hash()).count(), 1);
+    // Unknown block: the error is the only item.
+    let mut iter = chain.ancestors(&hash(&[1]));
+    assert!(iter.next().unwrap().is_err());
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn get_block_size() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let block = Block::empty(&genesis, &*signer);
+    let block_hash = *block.hash();
+    let expected_size = block.try_to_vec().unwrap().len();
+    chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.get_block_size(&block_hash).unwrap(), expected_size);
+    // Size of a block that was not saved in this session is read from the store.
+    let genesis_size = genesis.try_to_vec().unwrap().len();
+    let mut store = ChainStore::new(chain.store().owned_store(), chain.genesis().height());
+    assert_eq!(store.get_block_size(genesis.hash()).unwrap(), genesis_size);
+    assert_eq!(chain.get_block_size(genesis.hash()).unwrap(), genesis_size);
+    assert!(chain.get_block_size(&hash(&[1])).is_err());
+}
+
+#[test]
+fn finality_advanced() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let events = Arc::new(Mutex::new(vec![]));
+    let events1 = events.clone();
+    chain.set_on_finality_advanced(move |tip, newly_finalized| {
+        events1.lock().unwrap().push((tip.last_block_hash, newly_finalized))
+    });
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut blocks = vec![genesis];
+    // Skipping height 4 delays finality, so that several blocks become final at once.
+    for height in vec![1, 2, 3, 5, 6, 7] {
+        let block = Block::empty_with_height(blocks.last().unwrap(), height, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    let (b1, b2, b3, b5) =
+        (*blocks[1].hash(), *blocks[2].hash(), *blocks[3].hash(), *blocks[4].hash());
+    assert_eq!(chain.final_head().unwrap().last_block_hash, b5);
+    assert_eq!(*events.lock().unwrap(), vec![(b1, vec![b1]), (b5, vec![b2, b3, b5])]);
+}
+
+#[test]
+fn acceptable_time_difference() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.acceptable_time_difference = Duration::seconds(60);
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut outside = Block::empty_with_height(&genesis, 2, &*signer);
+    outside.mut_header().get_mut().inner_lite.timestamp =
+        to_timestamp(Utc::now() + Duration::seconds(70));
+    outside.mut_header().resign(&*signer);
+    let err = chain
+        .process_block(&None, outside, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidBlockFutureTime(_)));
+
+    let mut inside = Block::empty(&genesis, &*signer);
+    inside.mut_header().get_mut().inner_lite.timestamp =
+        to_timestamp(Utc::now() + Duration::seconds(50));
+    inside.mut_header().resign(&*signer);
+    chain.process_block(&None, inside, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().height, 1);
+}
+
+#[test]
+fn mock_clock_timestamp_bounds() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let now = genesis.header().timestamp() + Duration::seconds(100);
+    let clock = Arc::new(MockClock::new(now));
+    chain.set_clock(clock.clone());
+    chain.acceptable_time_difference = Duration::seconds(60);
+    let limit = now + Duration::seconds(60);
+    let block_at = |height, timestamp| {
+        let mut block = Block::empty_with_height(&genesis, height, &*signer);
+        block.mut_header().get_mut().inner_lite.timestamp = to_timestamp(timestamp);
+        block.mut_header().resign(&*signer);
+        block
+    };
+
+    let too_late = block_at(3, limit + Duration::nanoseconds(1));
+    let err = chain
+        .process_block(&None, too_late.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidBlockFutureTime(_)));
+
+    let too_early = block_at(4, genesis.header().timestamp() - Duration::nanoseconds(1));
+    let err = chain
+        .process_block(&None, too_early, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidBlockPastTime(_, _)));
+
+    let at_limit = block_at(2, limit);
+    chain.process_block(&None, at_limit, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().height, 2);
+
+    // Once the clock catches up, the block from the future is accepted.
+    clock.advance(Duration::seconds(1));
+    chain.process_block(&None, too_late, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().height, 3);
+}
+
+#[test]
+fn block_time_not_increasing() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let genesis_timestamp = genesis.header().raw_timestamp();
+    for (timestamp, same_time) in vec![(genesis_timestamp, true), (genesis_timestamp - 1, false)] {
+        let mut block = Block::empty(&genesis, &*signer);
+        block.mut_header().get_mut().inner_lite.timestamp = timestamp;
+        block.mut_header().resign(&*signer);
+        let err = chain
+            .process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap_err();
+        if same_time {
+            assert!(matches!(err.kind(), ErrorKind::DuplicateBlockTime(_)));
+            assert!(!err.is_bad_data());
+        } else {
+            assert!(matches!(err.kind(), ErrorKind::InvalidBlockPastTime(_, _)));
+            assert!(err.is_bad_data());
+        }
+    }
+    assert_eq!(chain.head().unwrap().height, 0);
+}
+
+#[test]
+fn validate_block() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    chain.validate_block(&None, &b1, &Provenance::PRODUCED).unwrap();
+    // Validation doesn't store anything.
+    assert!(chain.get_block(b1.hash()).is_err());
+    assert_eq!(chain.head().unwrap().height, 0);
+    assert_eq!(
+        chain.validate_block(&None, &b2, &Provenance::PRODUCED).unwrap_err().kind(),
+        ErrorKind::Orphan
+    );
+
+    let mut bad = Block::empty(&genesis, &*signer);
+    bad.mut_header().get_mut().inner_lite.timestamp = genesis.header().raw_timestamp();
+    bad.mut_header().resign(&*signer);
+    assert!(matches!(
+        chain.validate_block(&None, &bad, &Provenance::PRODUCED).unwrap_err().kind(),
+        ErrorKind::DuplicateBlockTime(_)
+    ));
+
+    chain.process_block(&None, b1, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    chain.validate_block(&None, &b2, &Provenance::PRODUCED).unwrap();
+}
+
+#[test]
+fn get_block_producer() {
+    init_test_logger();
+    let validators = vec!["test0".to_string(), "test1".to_string(), "test2".to_string()];
+    let (mut chain, _, signers) = setup_with_validators(validators.clone(), 1, 1, 10, 100);
+    let mut prev = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    for height in 1..=4 {
+        let block = Block::empty(&prev, &*signers[(height % 3) as usize]);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        prev = block;
+    }
+    // Heights of the current and the next epoch, including the ones after the head.
+    for height in 1..=20 {
+        assert_eq!(chain.get_block_producer(height).unwrap(), validators[height as usize % 3]);
+        assert_eq!(
+            chain.get_chunk_producer(height, 0).unwrap(),
+            validators[(height as usize + 1) % 3]
+        );
+    }
+    assert!(matches!(
+        chain.get_block_producer(21).unwrap_err().kind(),
+        ErrorKind::EpochOutOfBounds(_)
+    ));
+    assert!(matches!(
+        chain.get_chunk_producer(21, 0).unwrap_err().kind(),
+        ErrorKind::EpochOutOfBounds(_)
+    ));
+}
+
+#[test]
+fn finality_depth() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.finality_depth = Some(2);
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    // Skipped heights keep doomslug finality from advancing.
+    for height in vec![2, 4, 6, 8] {
+        let block = Block::empty_with_height(blocks.last().unwrap(), height, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+        let finalized_head = chain.finalized_head().unwrap();
+        assert_eq!(finalized_head.height, height - 2);
+    }
+    assert_eq!(chain.finalized_head().unwrap().last_block_hash, *blocks[3].hash());
+    assert_eq!(chain.final_head().unwrap().height, 0);
+
+    // Reorg reverting the finalized block at height 6 is rejected.
+    let fork = Block::empty_with_height(&blocks[2], 10, &*signer);
+    let fork_hash = *fork.hash();
+    let err =
+        chain.process_block(&None, fork, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ReorgTooDeep { depth: 4, limit: 2 });
+    assert!(chain.get_block(&fork_hash).is_err());
+
+    // Reorg on top of the finalized block is fine.
+    let fork = Block::empty_with_height(&blocks[3], 10, &*signer);
+    let fork_hash = *fork.hash();
+    chain.process_block(&None, fork, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, fork_hash);
+    assert_eq!(chain.finalized_head().unwrap().last_block_hash, *blocks[3].hash());
+}
+
+#[test]
+fn head_header_cloned() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    assert_eq!(chain.head_header_cloned().unwrap(), *genesis.header());
+    let b1 = Block::empty(&genesis, &*signer);
+    let b1_header = b1.header().clone();
+    chain.process_block(&None, b1, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    let read_only: &Chain = &chain;
+    assert_eq!(read_only.head_header_cloned().unwrap(), b1_header);
+    assert_eq!(chain.head_header().unwrap(), &b1_header);
+}
+
+#[test]
+fn get_incoming_receipts_range() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    for i in 1..=4 {
+        let block = Block::empty(&blocks[i - 1], &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    let fork = Block::empty(&blocks[2], &*signer);
+    chain.process_block(&None, fork.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    let receipt_proof = |refund| {
+        let receipt = Receipt::new_balance_refund(&"test".to_string(), refund);
+        let shard_proof = ShardProof { from_shard_id: 0, to_shard_id: 0, proof: vec![] };
+        ReceiptProof(vec![receipt], shard_proof)
+    };
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_incoming_receipt(blocks[1].hash(), 0, vec![receipt_proof(1)]);
+    store_update.save_incoming_receipt(blocks[3].hash(), 0, vec![receipt_proof(3)]);
+    store_update.save_incoming_receipt(fork.hash(), 0, vec![receipt_proof(30)]);
+    store_update.commit().unwrap();
+
+    let expected = vec![(1, receipt_proof(1).0), (3, receipt_proof(3).0)];
+    assert_eq!(chain.get_incoming_receipts_range(0, 0, 10).unwrap(), expected);
+    assert_eq!(chain.get_incoming_receipts_range(0, 2, 3).unwrap(), expected[1..].to_vec());
+    assert!(chain.get_incoming_receipts_range(1, 0, 10).unwrap().is_empty());
+}
+
+#[test]
+fn pause_processing() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b2_hash = *b2.hash();
+    let other_signer = InMemoryValidatorSigner::from_seed("other", KeyType::ED25519, "other");
+    let mut forged = b1.clone();
+    forged.mut_header().resign(&other_signer);
+
+    chain.pause_processing();
+    // Blocks get the orphan pool admission checks while paused.
+    let err =
+        chain.process_block(&None, forged, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidSignature);
+    assert_eq!(chain.orphans_len(), 0);
+    for block in vec![b1.clone(), b2] {
+        let err = chain
+            .process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Orphan);
+    }
+    assert!(chain.is_orphan(b1.hash()));
+    assert_eq!(chain.orphans_len(), 2);
+    assert_eq!(chain.head().unwrap().height, 0);
+    assert_eq!(chain.header_head().unwrap().height, 0);
+
+    let tip = chain.resume_processing(&None, |_| {}, |_| {}, |_| {}).unwrap().unwrap();
+    assert!(!chain.is_processing_paused());
+    assert_eq!(tip.last_block_hash, b2_hash);
+    assert_eq!(chain.head().unwrap().last_block_hash, b2_hash);
+    assert_eq!(chain.orphans_len(), 0);
+}
+
+#[test]
+fn get_locator() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    assert_eq!(chain.get_locator().unwrap(), vec![*genesis.hash()]);
+
+    let mut blocks = vec![genesis];
+    for height in (1..=10).filter(|height| *height != 7) {
+        let block = Block::empty_with_height(blocks.last().unwrap(), height, &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    let hash_at = |height| *blocks.iter().find(|b| b.header().height() == height).unwrap().hash();
+    // Heights 10, 9, 7, 3 and 0, where 7 is skipped.
+    let locator = chain.get_locator().unwrap();
+    assert_eq!(locator, vec![hash_at(10), hash_at(9), hash_at(3), hash_at(0)]);
+    assert_eq!(chain.find_common_header(&locator).unwrap().hash(), &hash_at(10));
+}
+
+#[test]
+fn gas_used_between() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut b1 = Block::empty(&genesis, &*signer);
+    let chunk1 = create_chunk_with_gas_used(&genesis, &*signer, vec![], 10);
+    set_chunks_and_resign(&mut b1, vec![chunk1.cloned_header()], &*signer);
+    // No new chunk, the one of b1 is carried over.
+    let b2 = Block::empty(&b1, &*signer);
+    let mut b3 = Block::empty(&b2, &*signer);
+    let chunk3 = create_chunk_with_gas_used(&b2, &*signer, vec![], 20);
+    set_chunks_and_resign(&mut b3, vec![chunk3.cloned_header()], &*signer);
+    let c2 = Block::empty(&b1, &*signer);
+    let mut store_update = chain.mut_store().store_update();
+    store_update.save_chunk(chunk1);
+    store_update.save_chunk(chunk3);
+    store_update.commit().unwrap();
+    for block in vec![b1.clone(), b2.clone(), b3.clone(), c2.clone()] {
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+
+    assert_eq!(chain.gas_used_between(genesis.hash(), b3.hash()).unwrap(), 30);
+    assert_eq!(chain.gas_used_between(b1.hash(), b3.hash()).unwrap(), 20);
+    assert_eq!(chain.gas_used_between(genesis.hash(), b2.hash()).unwrap(), 10);
+    assert_eq!(chain.gas_used_between(b3.hash(), b3.hash()).unwrap(), 0);
+    assert!(chain.gas_used_between(c2.hash(), b3.hash()).is_err());
+    assert!(chain.gas_used_between(b3.hash(), b1.hash()).is_err());
+}
+
+#[test]
+fn process_block_with_result() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let b2 = Block::empty(&b1, &*signer);
+    let b3 = Block::empty(&b2, &*signer);
+    let b4 = Block::empty(&b3, &*signer);
+    let c5 = Block::empty_with_height(&b1, 5, &*signer);
+    let b1_tip = Tip::from_header(b1.header());
+    let b4_tip = Tip::from_header(b4.header());
+    let b4_hash = *b4.hash();
+
+    let result = chain
+        .process_block_with_result(&None, b1, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap();
+    assert_eq!(
+        result,
+        ProcessBlockResult {
+            new_head: Some(b1_tip),
+            status: BlockStatus::Next,
+            orphans_unlocked: 0
+        }
+    );
+
+    for orphan in vec![b3, b4] {
+        chain.process_block(&None, orphan, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    }
+    let result = chain
+        .process_block_with_result(&None, b2, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap();
+    assert_eq!(
+        result,
+        ProcessBlockResult {
+            new_head: Some(b4_tip),
+            status: BlockStatus::Next,
+            orphans_unlocked: 2
+        }
+    );
+
+    let result = chain
+        .process_block_with_result(&None, c5, Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+        .unwrap();
+    assert_eq!(result.status, BlockStatus::Reorg(b4_hash));
+    assert_eq!(result.orphans_unlocked, 0);
+}
+
+#[test]
+fn reject_genesis_reprocess() {
+    init_test_logger();
+    let (mut chain, _, _) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let head = chain.head().unwrap();
+    let err =
+        chain.process_block(&None, genesis, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unfit("genesis reprocess".to_string()));
+    assert_eq!(chain.head().unwrap(), head);
+    assert_eq!(chain.orphans_len(), 0);
+}
+
+#[test]
+fn duplicate_block_below_head() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    for _ in 0..12 {
+        let block = Block::empty(blocks.last().unwrap(), &*signer);
+        blocks.push(block.clone());
+        chain.process_block(&None, block, Provenance::PRODUCED, |_| {}, |_| {}, |_| {}).unwrap();
+    }
+    let head = chain.head().unwrap();
+    assert_eq!(head.height, 12);
+
+    let duplicate = blocks[10].clone();
+    assert_eq!(duplicate.header().height(), 10);
+    let err = chain
+        .process_block(&None, duplicate, Provenance::NONE, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unfit("already known in store".to_string()));
+    assert_eq!(chain.head().unwrap(), head);
+}
+
+#[test]
+fn verify_store_integrity() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    for i in 1..=4 {
+        let block = Block::empty(&blocks[i - 1], &*signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        blocks.push(block);
+    }
+    assert!(chain.verify_store_integrity(0, 10).unwrap().is_empty());
+
+    // Corrupt the store behind the chain's back, as a crash could.
+    let mut corrupt_b4 = blocks[4].clone();
+    corrupt_b4.mut_header().get_mut().inner_lite.prev_state_root = hash(b"corrupt");
+    let mut store_update = chain.store().store().store_update();
+    store_update.delete(DBCol::ColBlock, blocks[2].hash().as_ref());
+    store_update.delete(DBCol::ColBlockHeader, blocks[3].hash().as_ref());
+    store_update.set_ser(DBCol::ColBlock, blocks[4].hash().as_ref(), &corrupt_b4).unwrap();
+    store_update.commit().unwrap();
+    // Drop the caches.
+    let store = chain.store().owned_store();
+    *chain.mut_store() = ChainStore::new(store, 0);
+
+    let (b2_hash, b3_hash, b4_hash) = (*blocks[2].hash(), *blocks[3].hash(), *blocks[4].hash());
+    assert_eq!(
+        chain.verify_store_integrity(0, 10).unwrap(),
+        vec![
+            IntegrityIssue::MissingBlock { height: 2, hash: b2_hash },
+            IntegrityIssue::MissingPrevHeader { height: 4, hash: b4_hash, prev_hash: b3_hash },
+            IntegrityIssue::StateRootMismatch { height: 4, hash: b4_hash },
+        ]
+    );
+    assert!(chain.verify_store_integrity(0, 1).unwrap().is_empty());
+}
+
+#[cfg(feature = "adversarial")]
+#[test]
+fn adv_trust_headers() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let b1 = Block::empty(&genesis, &*signer);
+    let mut b2 = Block::empty(&b1, &*signer);
+    b2.mut_header().get_mut().inner_lite.timestamp = b1.header().raw_timestamp() - 1;
+    b2.mut_header().resign(&*signer);
+    let b2_hash = *b2.hash();
+    chain.process_block(&None, b1, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap();
+    let err = chain
+        .process_block(&None, b2.clone(), Provenance::NONE, |_| {}, |_| {}, |_| {})
+        .unwrap_err();
+    match err.kind() {
+        ErrorKind::InvalidBlockPastTime(_, _) => {}
+        kind => panic!("Unexpected error {:?}", kind),
+    }
+
+    chain.adv_trust_headers(true);
+    chain.process_block(&None, b2, Provenance::NONE, |_| {}, |_| {}, |_| {}).unwrap();
+    assert_eq!(chain.head().unwrap().last_block_hash, b2_hash);
+}
@@ -1,7 +1,14 @@
-use near_chain::test_utils::setup;
-use near_chain::Block;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use near_chain::test_utils::{setup, KeyValueRuntime};
+use near_chain::{Block, Chain, ChainGenesis, DoomslugThresholdMode, ErrorKind, Provenance};
+use near_crypto::KeyType;
 use near_logger_utils::init_test_logger;
 use near_primitives::merkle::PartialMerkleTree;
+use near_primitives::validator_signer::InMemoryValidatorSigner;
+use near_store::test_utils::create_test_store;
 
 #[test]
 fn chain_sync_headers() {
@@ -24,3 +31,179 @@ fn chain_sync_headers() {
         .unwrap();
     assert_eq!(chain.header_head().unwrap().height, 4);
 }
+
+#[test]
+fn chain_bulk_process_headers() {
+    init_test_logger();
+    let chain_genesis = ChainGenesis::test();
+    let new_chain = || {
+        let runtime = Arc::new(KeyValueRuntime::new(create_test_store()));
+        Chain::new(runtime, &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap()
+    };
+    let mut chain = new_chain();
+    let mut serial_chain = new_chain();
+    let signer = InMemoryValidatorSigner::from_seed("test", KeyType::ED25519, "test");
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    let mut block_merkle_tree = PartialMerkleTree::default();
+    for i in 0..8 {
+        blocks.push(Block::empty_with_block_merkle_tree(
+            &blocks[i],
+            &signer,
+            &mut block_merkle_tree,
+        ));
+    }
+    let mut headers: Vec<_> = blocks.drain(1..).map(|block| block.header().clone()).collect();
+    serial_chain.sync_block_headers(headers.clone(), |_| panic!("Unexpected")).unwrap();
+    // Headers are processed in height order regardless of the input order.
+    headers.reverse();
+    chain.bulk_process_headers_with_validation_cache(headers, |_| panic!("Unexpected")).unwrap();
+    assert_eq!(chain.header_head().unwrap(), serial_chain.header_head().unwrap());
+    assert_eq!(chain.header_head().unwrap().height, 8);
+}
+
+#[test]
+fn sync_head_stalled() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let threshold = Duration::from_secs(60);
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    let mut block_merkle_tree = PartialMerkleTree::default();
+    for i in 0..4 {
+        blocks.push(Block::empty_with_block_merkle_tree(
+            &blocks[i],
+            &*signer,
+            &mut block_merkle_tree,
+        ));
+    }
+    let headers: Vec<_> = blocks[1..].iter().map(|block| block.header().clone()).collect();
+
+    let before_sync = Instant::now();
+    chain.sync_block_headers(headers[..2].to_vec(), |_| panic!("Unexpected")).unwrap();
+    assert!(!chain.sync_head_stalled(before_sync + threshold, threshold));
+    assert!(chain.sync_head_stalled(Instant::now() + threshold * 2, threshold));
+
+    // Known headers don't move the header head.
+    thread::sleep(Duration::from_millis(10));
+    let before_resync = Instant::now();
+    chain.sync_block_headers(headers[..2].to_vec(), |_| panic!("Unexpected")).unwrap();
+    assert!(chain.sync_head_stalled(before_resync + threshold, threshold));
+
+    let before_sync = Instant::now();
+    chain.sync_block_headers(headers, |_| panic!("Unexpected")).unwrap();
+    assert!(!chain.sync_head_stalled(before_sync + threshold, threshold));
+}
+
+#[test]
+fn sync_headers_incorrect_chunk_mask() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    let mut block_merkle_tree = PartialMerkleTree::default();
+    let mut block = Block::empty_with_block_merkle_tree(&genesis, &*signer, &mut block_merkle_tree);
+    block.mut_header().get_mut().inner_rest.chunk_mask.push(true);
+    block.mut_header().resign(&*signer);
+    let err = chain
+        .sync_block_headers(vec![block.header().clone()], |_| panic!("Unexpected"))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::IncorrectNumberOfChunkHeaders);
+    assert!(chain.get_block_header(block.hash()).is_err());
+    assert_eq!(chain.header_head().unwrap().height, 0);
+}
+
+#[test]
+fn sync_headers_not_linked() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    let mut block_merkle_tree = PartialMerkleTree::default();
+    for i in 0..3 {
+        blocks.push(Block::empty_with_block_merkle_tree(
+            &blocks[i],
+            &*signer,
+            &mut block_merkle_tree,
+        ));
+    }
+    let mut fork = Block::empty(&blocks[1], &*signer);
+    fork.mut_header().get_mut().inner_lite.timestamp += 1;
+    fork.mut_header().resign(&*signer);
+    let headers =
+        vec![blocks[1].header().clone(), fork.header().clone(), blocks[3].header().clone()];
+    let err = chain.sync_block_headers(headers, |_| panic!("Unexpected")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidHeaderChain(*fork.hash(), *blocks[3].hash()));
+    assert!(chain.get_block_header(blocks[1].hash()).is_err());
+    assert_eq!(chain.header_head().unwrap().height, 0);
+}
+
+#[test]
+fn verify_header_segment() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![chain.get_block(&chain.genesis().hash().clone()).unwrap().clone()];
+    let mut block_merkle_tree = PartialMerkleTree::default();
+    for i in 0..4 {
+        blocks.push(Block::empty_with_block_merkle_tree(
+            &blocks[i],
+            &*signer,
+            &mut block_merkle_tree,
+        ));
+    }
+    let trusted = blocks[0].header().clone();
+    let headers: Vec<_> = blocks[1..].iter().map(|block| block.header().clone()).collect();
+    chain.verify_header_segment(&trusted, &headers).unwrap();
+    chain.verify_header_segment(&trusted, &[]).unwrap();
+    // Nothing was written to the store.
+    assert_eq!(chain.header_head().unwrap().height, 0);
+
+    let gap = vec![headers[0].clone(), headers[2].clone()];
+    assert_eq!(
+        chain.verify_header_segment(&trusted, &gap).unwrap_err().kind(),
+        ErrorKind::InvalidHeaderChain(*headers[0].hash(), *headers[2].hash())
+    );
+
+    let mut past = headers[1].clone();
+    past.get_mut().inner_lite.timestamp = headers[0].raw_timestamp() - 1;
+    past.resign(&*signer);
+    match chain.verify_header_segment(&trusted, &[headers[0].clone(), past]).unwrap_err().kind() {
+        ErrorKind::InvalidBlockPastTime(_, _) => {}
+        kind => panic!("Unexpected error {:?}", kind),
+    }
+
+    let mut wrong_finality = headers[1].clone();
+    wrong_finality.get_mut().inner_rest.last_final_block = *headers[0].hash();
+    wrong_finality.resign(&*signer);
+    assert_eq!(
+        chain
+            .verify_header_segment(&trusted, &[headers[0].clone(), wrong_finality])
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidFinalityInfo
+    );
+}
+
+#[test]
+fn export_import_headers() {
+    init_test_logger();
+    let chain_genesis = ChainGenesis::test();
+    let new_chain = || {
+        let runtime = Arc::new(KeyValueRuntime::new(create_test_store()));
+        Chain::new(runtime, &chain_genesis, DoomslugThresholdMode::NoApprovals).unwrap()
+    };
+    let mut chain = new_chain();
+    let mut checkpoint_chain = new_chain();
+    let signer = InMemoryValidatorSigner::from_seed("test", KeyType::ED25519, "test");
+    let mut prev = chain.get_block(&chain.genesis().hash().clone()).unwrap().clone();
+    for _ in 0..4 {
+        let block = Block::empty(&prev, &signer);
+        chain
+            .process_block(&None, block.clone(), Provenance::PRODUCED, |_| {}, |_| {}, |_| {})
+            .unwrap();
+        prev = block;
+    }
+
+    let headers = chain.export_headers(1, 10).unwrap();
+    assert_eq!(headers.iter().map(|header| header.height()).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    assert_eq!(headers.last().unwrap().hash(), prev.hash());
+
+    checkpoint_chain.import_headers(headers).unwrap();
+    assert_eq!(checkpoint_chain.header_head().unwrap().last_block_hash, *prev.hash());
+}
@@ -109,7 +109,10 @@ impl Client {
         } else {
             DoomslugThresholdMode::NoApprovals
         };
-        let chain = Chain::new(runtime_adapter.clone(), &chain_genesis, doomslug_threshold_mode)?;
+        let mut chain =
+            Chain::new(runtime_adapter.clone(), &chain_genesis, doomslug_threshold_mode)?;
+        chain.archival = config.archive;
+        chain.restore_orphans()?;
         let shards_mgr = ShardsManager::new(
             validator_signer.as_ref().map(|x| x.validator_id().clone()),
             runtime_adapter.clone(),
@@ -1175,6 +1178,36 @@ impl Client {
         unwrapped_accepted_blocks
     }
 
+    /// Process orphans restored from the store whose previous blocks became known while the node
+    /// was down.
+    pub fn process_restored_orphans(&mut self) -> Result<Vec<AcceptedBlock>, Error> {
+        let accepted_blocks = Arc::new(RwLock::new(vec![]));
+        let blocks_missing_chunks = Arc::new(RwLock::new(vec![]));
+        let challenges = Arc::new(RwLock::new(vec![]));
+        let me =
+            self.validator_signer.as_ref().map(|validator_signer| validator_signer.validator_id());
+        let result = self.chain.check_restored_orphans(
+            &me.map(|x| x.clone()),
+            |accepted_block| accepted_blocks.write().unwrap().push(accepted_block),
+            |missing_chunks| blocks_missing_chunks.write().unwrap().push(missing_chunks),
+            |challenge| challenges.write().unwrap().push(challenge),
+        );
+        self.send_challenges(challenges);
+        result?;
+
+        let header_head = self.chain.header_head()?;
+        let protocol_version =
+            self.runtime_adapter.get_epoch_protocol_version(&header_head.epoch_id)?;
+        self.shards_mgr.request_chunks(
+            blocks_missing_chunks.write().unwrap().drain(..).flatten(),
+            &header_head,
+            protocol_version,
+        );
+
+        let unwrapped_accepted_blocks = accepted_blocks.write().unwrap().drain(..).collect();
+        Ok(unwrapped_accepted_blocks)
+    }
+
     pub fn is_validator(&self, epoch_id: &EpochId, block_hash: &CryptoHash) -> bool {
         match self.validator_signer.as_ref() {
             None => false,
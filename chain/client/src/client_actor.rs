@@ -5,7 +5,7 @@ use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use actix::{Actor, Addr, Arbiter, Context, Handler};
+use actix::{Actor, Addr, Arbiter, Context, Handler, Running};
 use actix_rt::ArbiterHandle;
 use chrono::Duration as OldDuration;
 use chrono::{DateTime, Utc};
@@ -187,9 +187,23 @@ impl Actor for ClientActor {
         // Start catchup job.
         self.catchup(ctx);
 
+        // Process orphans saved on the last shutdown whose previous blocks are known by now.
+        match self.client.process_restored_orphans() {
+            Ok(accepted_blocks) => self.process_accepted_blocks(accepted_blocks),
+            Err(err) => error!(target: "client", "Failed to process restored orphans: {}", err),
+        }
+
         // Start periodic logging of current state of the client.
         self.log_summary(ctx);
     }
+
+    /// Save the orphan pool, so that it's restored on the next start.
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        if let Err(err) = self.client.chain.flush_orphans() {
+            error!(target: "client", "Failed to save orphans: {}", err);
+        }
+        Running::Stop
+    }
 }
 
 impl Handler<NetworkClientMessages> for ClientActor {
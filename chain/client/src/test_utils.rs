@@ -92,6 +92,7 @@ pub fn setup(
         transaction_validity_period,
         epoch_length,
         protocol_version: PROTOCOL_VERSION,
+        genesis_receipts: HashMap::new(),
     };
     let doomslug_threshold_mode = if enable_doomslug {
         DoomslugThresholdMode::TwoThirds
@@ -380,6 +380,7 @@ fn test_verify_chunk_invalid_state_challenge() {
         let chain = &mut client.chain;
         let adapter = chain.runtime_adapter.clone();
         let epoch_length = chain.epoch_length;
+        let acceptable_time_difference = chain.acceptable_time_difference;
         let empty_block_pool = OrphanBlockPool::new();
         let empty_chunks_pool = MissingChunksPool::new();
         let chain_genesis = ChainGenesis::from(&genesis);
@@ -396,6 +397,10 @@ fn test_verify_chunk_invalid_state_challenge() {
             &genesis_block,
             transaction_validity_period,
             None,
+            None,
+            None,
+            acceptable_time_difference,
+            None,
         );
 
         chain_update.create_chunk_state_challenge(&last_block, &block, &block.chunks()[0]).unwrap()
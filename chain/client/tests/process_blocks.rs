@@ -1405,6 +1405,74 @@ fn test_gc_chunk_tail() {
     }
 }
 
+#[test]
+fn test_tx_observer() {
+    let genesis = Genesis::test(vec!["test0", "test1"], 1);
+    let mut env = TestEnv::new_with_runtime(
+        ChainGenesis::test(),
+        1,
+        1,
+        create_nightshade_runtimes(&genesis, 1),
+    );
+    let observed = Arc::new(RwLock::new(vec![]));
+    let observed1 = observed.clone();
+    env.clients[0].chain.set_tx_observer(move |tx_hash, tx| {
+        assert_eq!(tx_hash, &tx.get_hash());
+        observed1.write().unwrap().push(*tx_hash);
+    });
+    let genesis_hash = *env.clients[0].chain.genesis().hash();
+    let signer = InMemorySigner::from_seed("test0", KeyType::ED25519, "test0");
+    let tx = SignedTransaction::send_money(
+        1,
+        "test0".to_string(),
+        "test1".to_string(),
+        &signer,
+        100,
+        genesis_hash,
+    );
+    let tx_hash = tx.get_hash();
+    env.clients[0].process_tx(tx, false, false);
+    for i in 1..5 {
+        env.produce_block(0, i);
+    }
+    assert_eq!(*observed.read().unwrap(), vec![tx_hash]);
+    assert!(env.clients[0].chain.get_final_transaction_result(&tx_hash).is_ok());
+}
+
+#[test]
+fn test_get_transaction_block() {
+    let genesis = Genesis::test(vec!["test0", "test1"], 1);
+    let mut env = TestEnv::new_with_runtime(
+        ChainGenesis::test(),
+        1,
+        1,
+        create_nightshade_runtimes(&genesis, 1),
+    );
+    let genesis_hash = *env.clients[0].chain.genesis().hash();
+    let signer = InMemorySigner::from_seed("test0", KeyType::ED25519, "test0");
+    let tx = SignedTransaction::send_money(
+        1,
+        "test0".to_string(),
+        "test1".to_string(),
+        &signer,
+        100,
+        genesis_hash,
+    );
+    let tx_hash = tx.get_hash();
+    assert!(env.clients[0].chain.get_transaction_block(&tx_hash).is_err());
+    env.clients[0].process_tx(tx, false, false);
+    for i in 1..5 {
+        env.produce_block(0, i);
+    }
+
+    let block_hash = env.clients[0].chain.get_transaction_block(&tx_hash).unwrap();
+    let block = env.clients[0].chain.get_block(&block_hash).unwrap().clone();
+    let chunk_header = block.chunks()[0].clone();
+    assert_eq!(chunk_header.height_included(), block.header().height());
+    let chunk = env.clients[0].chain.get_chunk(&chunk_header.chunk_hash()).unwrap();
+    assert!(chunk.transactions().iter().any(|tx| tx.get_hash() == tx_hash));
+}
+
 #[test]
 fn test_gc_execution_outcome() {
     let epoch_length = 5;
@@ -2213,6 +2281,66 @@ fn test_catchup_gas_price_change() {
     assert_eq!(chunk_extra_after_sync, expected_chunk_extra);
 }
 
+/// Blocks building on top of a state synced block should be processed without the node having
+/// applied any of the previous blocks.
+#[test]
+fn test_process_blocks_after_state_sync() {
+    init_test_logger();
+    let epoch_length = 5;
+    let mut genesis = Genesis::test(vec!["test0", "test1"], 1);
+    genesis.config.epoch_length = epoch_length;
+    let chain_genesis = ChainGenesis::from(&genesis);
+    let mut env =
+        TestEnv::new_with_runtime(chain_genesis, 2, 1, create_nightshade_runtimes(&genesis, 2));
+    let mut blocks = vec![];
+    for i in 1..=8 {
+        let block = env.clients[0].produce_block(i).unwrap().unwrap();
+        blocks.push(block.clone());
+        env.process_block(0, block, Provenance::PRODUCED);
+    }
+
+    // The second node only knows headers and syncs state as of the first block of the epoch.
+    let sync_block = blocks[5].clone();
+    let prev_sync_block = blocks[4].clone();
+    let sync_hash = *sync_block.hash();
+    assert!(env.clients[0].chain.check_sync_hash_validity(&sync_hash).unwrap());
+    env.clients[1].sync_block_headers(blocks.iter().map(|b| b.header().clone()).collect()).unwrap();
+    env.clients[1].chain.reset_data_pre_state_sync(sync_hash).unwrap();
+    env.clients[1].chain.save_block(&prev_sync_block).unwrap();
+
+    let state_sync_header = env.clients[0].chain.get_state_response_header(0, sync_hash).unwrap();
+    let state_root = match &state_sync_header {
+        ShardStateSyncResponseHeader::V1(header) => header.chunk.header.inner.prev_state_root,
+        ShardStateSyncResponseHeader::V2(header) => {
+            *header.chunk.cloned_header().take_inner().prev_state_root()
+        }
+    };
+    let state_root_node =
+        env.clients[0].runtime_adapter.get_state_root_node(0, &state_root).unwrap();
+    let num_parts = get_num_state_parts(state_root_node.memory_usage);
+    env.clients[1].chain.set_state_header(0, sync_hash, state_sync_header).unwrap();
+    for i in 0..num_parts {
+        let part = env.clients[0].chain.get_state_response_part(0, i, sync_hash).unwrap();
+        env.clients[1].chain.set_state_part(0, sync_hash, i, num_parts, &part).unwrap();
+    }
+    env.clients[1].chain.set_state_finalize(0, sync_hash, num_parts).unwrap();
+    env.clients[1]
+        .chain
+        .reset_heads_post_state_sync(&None, sync_hash, |_| {}, |_| {}, |_| {})
+        .unwrap();
+    assert_eq!(env.clients[1].chain.head().unwrap().last_block_hash, *prev_sync_block.hash());
+
+    for block in blocks[5..].iter() {
+        env.process_block(1, block.clone(), Provenance::NONE);
+    }
+    let head = env.clients[1].chain.head().unwrap();
+    assert_eq!(head.last_block_hash, *blocks[7].hash());
+    let chunk_extra = env.clients[1].chain.get_chunk_extra(blocks[7].hash(), 0).unwrap().clone();
+    let expected_chunk_extra =
+        env.clients[0].chain.get_chunk_extra(blocks[7].hash(), 0).unwrap().clone();
+    assert_eq!(chunk_extra, expected_chunk_extra);
+}
+
 #[test]
 fn test_block_execution_outcomes() {
     let epoch_length = 5;
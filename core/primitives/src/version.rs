@@ -13,7 +13,7 @@ pub struct Version {
 pub type DbVersion = u32;
 
 /// Current version of the database.
-pub const DB_VERSION: DbVersion = 25;
+pub const DB_VERSION: DbVersion = 26;
 
 /// Protocol version type.
 pub use near_primitives_core::types::ProtocolVersion;
@@ -107,6 +107,9 @@ pub enum ProtocolFeature {
     AltBn128,
     #[cfg(feature = "protocol_feature_restore_receipts_after_fix")]
     RestoreReceiptsAfterFix,
+    /// Break ties between heads at the same height by the smaller block hash
+    #[cfg(feature = "protocol_feature_fork_choice_tie_break")]
+    ForkChoiceTieBreak,
 }
 
 /// Current latest stable version of the protocol.
@@ -117,7 +120,7 @@ pub const PROTOCOL_VERSION: ProtocolVersion = 46;
 
 /// Current latest nightly version of the protocol.
 #[cfg(feature = "nightly_protocol")]
-pub const PROTOCOL_VERSION: ProtocolVersion = 114;
+pub const PROTOCOL_VERSION: ProtocolVersion = 115;
 
 impl ProtocolFeature {
     pub const fn protocol_version(self) -> ProtocolVersion {
@@ -145,6 +148,8 @@ impl ProtocolFeature {
             ProtocolFeature::BlockHeaderV3 => 109,
             #[cfg(feature = "protocol_feature_restore_receipts_after_fix")]
             ProtocolFeature::RestoreReceiptsAfterFix => 112,
+            #[cfg(feature = "protocol_feature_fork_choice_tie_break")]
+            ProtocolFeature::ForkChoiceTieBreak => 115,
         }
     }
 }
@@ -226,6 +226,13 @@ pub fn apply_store_migrations(path: &String, near_config: &NearConfig) {
         info!(target: "near", "Migrate DB from version 24 to 25");
         migrate_24_to_25(&path);
     }
+    if db_version <= 25 {
+        // version 25 => 26: add ColOrphans
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 25 to 26");
+        let store = create_store(&path);
+        set_store_version(&store, 26);
+    }
     #[cfg(feature = "nightly_protocol")]
     {
         let store = create_store(&path);